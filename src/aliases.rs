@@ -0,0 +1,302 @@
+use crate::changepoints;
+use crate::comparison::Settings;
+use crate::metrics::{Registry, Suite};
+use crate::revisions::{db_revision_known, revision_label};
+use crate::testcases;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, NO_PARAMS};
+use std::collections::HashMap;
+
+/// Renames followed at most in a row, which also stops at cycles.
+const MAX_RENAMES: usize = 100;
+
+/// Runs of `old_path` at revisions before `revision` belong to the test at
+/// `new_path`. From `revision` on, the test was reported as `new_path`.
+#[derive(Debug, Serialize)]
+pub struct Alias {
+    pub suite: String,
+    pub old_path: String,
+    pub new_path: String,
+    pub revision: String,
+}
+
+/// The aliases of one suite.
+pub struct Aliases {
+    /// `old_path -> (new_path, sequence of the rename)`.
+    renames: HashMap<String, (String, i64)>,
+}
+
+impl Aliases {
+    /// Path of the test that a run of `path` at `sequence` belongs to,
+    /// following chains of renames.
+    pub fn resolve<'a>(&'a self, path: &'a str, sequence: i64) -> &'a str {
+        let mut path = path;
+        for _ in 0..MAX_RENAMES {
+            match self.renames.get(path) {
+                Some((new_path, renamed_at)) if sequence < *renamed_at => path = new_path.as_str(),
+                _ => break,
+            }
+        }
+        path
+    }
+
+    /// `path` and every path whose runs may resolve to it.
+    pub fn sources<'a>(&'a self, path: &'a str) -> Vec<&'a str> {
+        let mut sources = vec![path];
+        let mut i = 0;
+        while i < sources.len() && sources.len() < MAX_RENAMES {
+            let target = sources[i];
+            for (old_path, (new_path, _)) in self.renames.iter() {
+                if new_path == target && !sources.contains(&old_path.as_str()) {
+                    sources.push(old_path);
+                }
+            }
+            i += 1;
+        }
+        sources
+    }
+}
+
+pub fn db_aliases(conn: &Connection, suite: &str) -> rusqlite::Result<Aliases> {
+    let mut stmt = conn.prepare_cached(concat!(
+        "SELECT a.old_path, a.new_path, r.sequence ",
+        "FROM test_aliases a JOIN revisions r ON r.revision = a.revision ",
+        "WHERE a.suite = ?1"
+    ))?;
+    let renames = stmt
+        .query_map(&[&suite], |row| {
+            Ok((row.get(0)?, (row.get(1)?, row.get(2)?)))
+        })?
+        .filter_map(|r| r.ok())
+        .collect();
+    Ok(Aliases { renames })
+}
+
+pub fn db_all_aliases(conn: &Connection) -> rusqlite::Result<Vec<Alias>> {
+    let mut stmt = conn.prepare(concat!(
+        "SELECT a.suite, a.old_path, a.new_path, a.revision ",
+        "FROM test_aliases a LEFT JOIN revisions r ON r.revision = a.revision ",
+        "ORDER BY a.suite, r.sequence, a.old_path"
+    ))?;
+    let aliases = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok(Alias {
+                suite: row.get(0)?,
+                old_path: row.get(1)?,
+                new_path: row.get(2)?,
+                revision: row.get(3)?,
+            })
+        })?
+        .filter_map(|r| r.ok())
+        .collect();
+    Ok(aliases)
+}
+
+/// Declares a rename, replacing an earlier alias of `old_path`, and redoes
+/// change point detection for both paths.
+pub fn add_alias(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
+    alias: &Alias,
+) -> Result<(), String> {
+    let suite = registry
+        .suite(&alias.suite)
+        .ok_or_else(|| format!("unknown suite '{}'", alias.suite))?;
+    if alias.old_path == alias.new_path {
+        return Err("old and new path are the same".to_string());
+    }
+    let mut conn = db.get().map_err(|e| e.to_string())?;
+    if !db_revision_known(&conn, &alias.revision).map_err(|e| e.to_string())? {
+        return Err(format!("unknown revision {}", alias.revision));
+    }
+    let aliases = db_aliases(&conn, &suite.name).map_err(|e| e.to_string())?;
+    if aliases
+        .sources(&alias.old_path)
+        .contains(&alias.new_path.as_str())
+    {
+        return Err(format!(
+            "{} is already an alias of {}",
+            alias.new_path, alias.old_path
+        ));
+    }
+
+    conn.execute(
+        concat!(
+            "INSERT OR REPLACE INTO test_aliases (suite, old_path, new_path, revision) ",
+            "VALUES (?1, ?2, ?3, ?4)"
+        ),
+        params![alias.suite, alias.old_path, alias.new_path, alias.revision],
+    )
+    .map_err(|e| e.to_string())?;
+    // The new path may not have runs of its own yet.
+    testcases::db_register(&conn, registry, &suite.name, &alias.new_path)
+        .map_err(|e| e.to_string())?;
+    redetect(&mut conn, registry, settings, suite, alias)
+}
+
+pub fn remove_alias(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
+    suite: &str,
+    old_path: &str,
+) -> Result<(), String> {
+    let suite = registry
+        .suite(suite)
+        .ok_or_else(|| format!("unknown suite '{}'", suite))?;
+    let mut conn = db.get().map_err(|e| e.to_string())?;
+    let alias = db_all_aliases(&conn)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|a| a.suite == suite.name && a.old_path == old_path)
+        .ok_or_else(|| format!("{} has no alias", old_path))?;
+    conn.execute(
+        "DELETE FROM test_aliases WHERE suite = ?1 AND old_path = ?2",
+        params![suite.name, old_path],
+    )
+    .map_err(|e| e.to_string())?;
+    redetect(&mut conn, registry, settings, suite, &alias)
+}
+
+fn redetect(
+    conn: &mut Connection,
+    registry: &Registry,
+    settings: &Settings,
+    suite: &Suite,
+    alias: &Alias,
+) -> Result<(), String> {
+    let tests = [
+        (suite.name.clone(), alias.old_path.clone()),
+        (suite.name.clone(), alias.new_path.clone()),
+    ];
+    changepoints::detect(conn, registry, settings, &tests).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// One line per alias, for the command line.
+pub fn describe(alias: &Alias) -> String {
+    format!(
+        "{}: {} -> {} from {}",
+        alias.suite,
+        alias.old_path,
+        alias.new_path,
+        revision_label(&alias.revision)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::revisions::{db_record_revision, Commit};
+    use crate::schema;
+
+    /// `a` became `b` at 10, and `b` became `c` at 20.
+    fn chain() -> Aliases {
+        let mut renames = HashMap::new();
+        renames.insert("a".to_string(), ("b".to_string(), 10));
+        renames.insert("b".to_string(), ("c".to_string(), 20));
+        Aliases { renames }
+    }
+
+    #[test]
+    fn resolves_chains_of_renames() {
+        let aliases = chain();
+        assert_eq!(aliases.resolve("a", 5), "c");
+        assert_eq!(aliases.resolve("b", 15), "c");
+        assert_eq!(aliases.resolve("a", 15), "a");
+        assert_eq!(aliases.resolve("b", 20), "b");
+        assert_eq!(aliases.resolve("c", 5), "c");
+        assert_eq!(aliases.resolve("d", 5), "d");
+    }
+
+    #[test]
+    fn finds_all_sources_of_a_path() {
+        let aliases = chain();
+        let mut sources = aliases.sources("c");
+        sources.sort();
+        assert_eq!(sources, vec!["a", "b", "c"]);
+        assert_eq!(aliases.sources("a"), vec!["a"]);
+    }
+
+    #[test]
+    fn stops_at_cycles() {
+        let mut renames = HashMap::new();
+        renames.insert("a".to_string(), ("b".to_string(), 10));
+        renames.insert("b".to_string(), ("a".to_string(), 10));
+        let aliases = Aliases { renames };
+        assert_eq!(aliases.resolve("a", 5), "a");
+        assert_eq!(aliases.sources("a").len(), 2);
+    }
+
+    #[test]
+    fn rejects_aliases_that_would_form_a_cycle() {
+        // A single connection, so that every checkout sees the same database.
+        let db = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        let registry = Registry::new(Vec::new(), Vec::new()).unwrap();
+        let settings = Settings::default();
+        {
+            let mut conn = db.get().unwrap();
+            schema::migrate(&mut conn).unwrap();
+            schema::ensure_suites(&conn, &registry).unwrap();
+            db_record_revision(
+                &conn,
+                "812345",
+                "",
+                &Commit::default(),
+                settings.lowest_revision,
+            )
+            .unwrap();
+        }
+        let alias = |old_path: &str, new_path: &str, revision: &str| Alias {
+            suite: "csb".to_string(),
+            old_path: old_path.to_string(),
+            new_path: new_path.to_string(),
+            revision: revision.to_string(),
+        };
+
+        assert_eq!(
+            add_alias(
+                &db,
+                &registry,
+                &settings,
+                &alias("a.csb", "b.csb", "812345")
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            add_alias(
+                &db,
+                &registry,
+                &settings,
+                &alias("b.csb", "a.csb", "812345")
+            ),
+            Err("a.csb is already an alias of b.csb".to_string())
+        );
+        assert_eq!(
+            add_alias(
+                &db,
+                &registry,
+                &settings,
+                &alias("c.csb", "c.csb", "812345")
+            ),
+            Err("old and new path are the same".to_string())
+        );
+        assert_eq!(
+            add_alias(
+                &db,
+                &registry,
+                &settings,
+                &alias("c.csb", "d.csb", "999999")
+            ),
+            Err("unknown revision 999999".to_string())
+        );
+        let aliases = db_all_aliases(&db.get().unwrap()).unwrap();
+        assert_eq!(aliases.len(), 1);
+        assert_eq!(describe(&aliases[0]), "csb: a.csb -> b.csb from r812345");
+    }
+}
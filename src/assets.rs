@@ -0,0 +1,232 @@
+use crate::error::{self, Error};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io::{self, Cursor};
+use std::path::{Component, Path, PathBuf};
+use tokio::prelude::stream::{self, Once};
+use tower_web::util::buf_stream::StdStream;
+
+/// Compiled into the binary, so that the pages and their scripts always come
+/// from the same version.
+static EMBEDDED: &[(&str, &[u8])] = &[
+    ("Chart.min.js", include_bytes!("../static/Chart.min.js")),
+    ("icon.png", include_bytes!("../static/icon.png")),
+    ("style.css", include_bytes!("../static/style.css")),
+    ("table.js", include_bytes!("../static/table.js")),
+];
+
+/// Asset URLs don't change between versions, so clients revalidate with the
+/// ETag on every use, which costs a 304 response when nothing changed.
+const CACHE_CONTROL: &str = "public, no-cache";
+
+/// `Vec<u8>` can't be used as a response body, so the content is sent as a
+/// stream of a single chunk.
+pub type Body = StdStream<Once<Cursor<Vec<u8>>, io::Error>>;
+
+pub fn stream(content: Vec<u8>) -> Body {
+    StdStream::new(stream::once(Ok(Cursor::new(content))))
+}
+
+#[derive(Debug)]
+pub struct Assets {
+    /// Serves the files of this directory instead of the embedded copies.
+    override_dir: Option<PathBuf>,
+    etags: HashMap<&'static str, String>,
+}
+
+impl Assets {
+    pub fn new(override_dir: Option<PathBuf>) -> Self {
+        let etags = EMBEDDED
+            .iter()
+            .map(|(name, content)| (*name, etag(content)))
+            .collect();
+        Self {
+            override_dir,
+            etags,
+        }
+    }
+
+    pub fn response(
+        &self,
+        rel_path: &Path,
+        if_none_match: Option<&str>,
+    ) -> error::Result<http::Response<Vec<u8>>> {
+        let not_found = || Error::NotFound(format!("no asset {}", rel_path.display()));
+        let forbidden = || Error::Forbidden(format!("not an asset: {}", rel_path.display()));
+        // Only plain names below the asset directory; no `..`, no absolute
+        // paths.
+        if !rel_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(forbidden());
+        }
+        let content_type = content_type(rel_path).ok_or_else(forbidden)?;
+
+        let (content, etag) = match &self.override_dir {
+            Some(dir) => {
+                // Symlinks may still point outside of the directory.
+                let dir = dir.canonicalize().map_err(|_| not_found())?;
+                let path = dir.join(rel_path).canonicalize().map_err(|_| not_found())?;
+                if !path.starts_with(&dir) {
+                    return Err(forbidden());
+                }
+                let content = std::fs::read(path).map_err(|_| not_found())?;
+                let etag = etag(&content);
+                (content, etag)
+            }
+            None => {
+                let name = rel_path.to_str().ok_or_else(not_found)?;
+                let (name, content) = EMBEDDED
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .ok_or_else(not_found)?;
+                (content.to_vec(), self.etags[*name].clone())
+            }
+        };
+
+        let mut response = http::Response::builder();
+        response
+            .header(http::header::CONTENT_TYPE, content_type)
+            .header(http::header::CACHE_CONTROL, CACHE_CONTROL)
+            .header(http::header::ETAG, etag.as_str());
+        let body = if if_none_match.is_some_and(|tags| matches_etag(tags, &etag)) {
+            response.status(http::StatusCode::NOT_MODIFIED);
+            Vec::new()
+        } else {
+            content
+        };
+        Ok(response.body(body)?)
+    }
+}
+
+/// Also the allowlist of file types: other files are never served, even
+/// from the override directory.
+fn content_type(path: &Path) -> Option<&'static str> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("js") => Some("application/javascript; charset=utf-8"),
+        Some("css") => Some("text/css; charset=utf-8"),
+        Some("png") => Some("image/png"),
+        Some("svg") => Some("image/svg+xml"),
+        Some("json") => Some("application/json"),
+        _ => None,
+    }
+}
+
+/// Quoted hash of the content.
+fn etag(content: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(content);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// `If-None-Match` holds a list of ETags, or `*`.
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn status(assets: &Assets, path: &str) -> http::StatusCode {
+        match assets.response(Path::new(path), None) {
+            Ok(response) => response.status(),
+            Err(e) => e.status(),
+        }
+    }
+
+    /// An empty directory of its own below the system's temp directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("benchtable-assets-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_asset_directory() {
+        let dir = temp_dir("traversal");
+        fs::write(dir.join("x.js"), "").unwrap();
+        for assets in [Assets::new(None), Assets::new(Some(dir.join("static")))].iter() {
+            for path in ["../x.js", "/etc/passwd", "a/../../x.js", "config.toml"].iter() {
+                assert_eq!(
+                    status(assets, path),
+                    http::StatusCode::FORBIDDEN,
+                    "{}",
+                    path
+                );
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_assets_are_not_found() {
+        let dir = temp_dir("missing");
+        assert_eq!(
+            status(&Assets::new(None), "missing.js"),
+            http::StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&Assets::new(Some(dir.clone())), "missing.js"),
+            http::StatusCode::NOT_FOUND
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_out_of_the_override_directory() {
+        let dir = temp_dir("symlinks");
+        let outside = temp_dir("symlinks-outside");
+        fs::write(dir.join("inside.js"), "").unwrap();
+        fs::write(outside.join("secret.js"), "").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.js"), dir.join("link.js")).unwrap();
+
+        let assets = Assets::new(Some(dir.clone()));
+        assert_eq!(status(&assets, "inside.js"), http::StatusCode::OK);
+        assert_eq!(status(&assets, "link.js"), http::StatusCode::FORBIDDEN);
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&outside);
+    }
+
+    #[test]
+    fn serves_embedded_assets_with_etags() {
+        let assets = Assets::new(None);
+        let expected_etag = etag(include_bytes!("../static/table.js"));
+
+        let response = assets.response(Path::new("table.js"), None).unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/javascript; charset=utf-8"
+        );
+        assert_eq!(
+            response.headers()[http::header::ETAG],
+            expected_etag.as_str()
+        );
+        assert_eq!(
+            response.body().as_slice(),
+            &include_bytes!("../static/table.js")[..]
+        );
+
+        let if_none_match = format!("\"other\", W/{}", expected_etag);
+        let response = assets
+            .response(Path::new("table.js"), Some(&if_none_match))
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+        assert!(response.body().is_empty());
+
+        let response = assets
+            .response(Path::new("table.js"), Some("\"other\""))
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+    }
+}
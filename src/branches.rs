@@ -0,0 +1,83 @@
+use crate::metrics::Registry;
+use itertools::Itertools;
+use rusqlite::{Connection, NO_PARAMS};
+use std::collections::HashSet;
+
+/// Runs that don't name a branch belong to the main line.
+pub const MAIN_LINE: &str = "";
+
+/// Ancestors visited at most while looking for a merge base.
+const MAX_ANCESTORS: usize = 10_000;
+
+pub fn branch_label(branch: &str) -> &str {
+    if branch == MAIN_LINE {
+        "trunk"
+    } else {
+        branch
+    }
+}
+
+/// All branches that reported runs, main line first.
+pub fn db_branches(conn: &Connection, registry: &Registry) -> rusqlite::Result<Vec<String>> {
+    let query = registry
+        .suites
+        .iter()
+        .map(|suite| format!("SELECT branch FROM {}", suite.table))
+        .join(" UNION ")
+        + " ORDER BY branch";
+    Ok(conn
+        .prepare(&query)?
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .filter_map(|r| r.ok())
+        .collect())
+}
+
+/// Newest main line revision that `head` descends from. Follows the parents
+/// recorded in the revisions table; without them, e.g. for SVN branches
+/// whose runs don't report the copy source, it is the newest main line
+/// revision before the branch's first revision.
+pub fn db_merge_base(
+    conn: &Connection,
+    branch: &str,
+    head: &str,
+) -> rusqlite::Result<Option<String>> {
+    let mut stmt =
+        conn.prepare_cached("SELECT branch, sequence, parents FROM revisions WHERE revision = ?1")?;
+    let mut best: Option<(i64, String)> = None;
+    let mut visited = HashSet::new();
+    let mut pending = vec![head.to_string()];
+    while let Some(revision) = pending.pop() {
+        if visited.len() >= MAX_ANCESTORS || !visited.insert(revision.clone()) {
+            continue;
+        }
+        let mut rows = stmt.query_map(&[&revision], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let (revision_branch, sequence, parents) = match rows.next() {
+            Some(row) => row?,
+            None => continue,
+        };
+        if revision_branch == MAIN_LINE && revision != head {
+            if best.as_ref().is_none_or(|(s, _)| sequence > *s) {
+                best = Some((sequence, revision));
+            }
+            continue;
+        }
+        pending.extend(parents.split_whitespace().map(String::from));
+    }
+    if let Some((_, revision)) = best {
+        return Ok(Some(revision));
+    }
+
+    let mut stmt = conn.prepare_cached(concat!(
+        "SELECT revision FROM revisions WHERE branch = ?1 AND sequence < ",
+        "(SELECT MIN(sequence) FROM revisions WHERE branch = ?2) ",
+        "ORDER BY sequence DESC LIMIT 1"
+    ))?;
+    let mut rows = stmt.query_map(&[MAIN_LINE, branch], |row| row.get(0))?;
+    rows.next().transpose()
+}
@@ -0,0 +1,373 @@
+use crate::comparison::Settings;
+use crate::error;
+use crate::graphs::{db_revision_history_for_file, RevisionInfos};
+use crate::machines::db_test_series;
+use crate::metrics::{Registry, Suite};
+use crate::noise;
+use crate::stats;
+use crate::testcases::{db_test, db_test_id};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, ToSql, NO_PARAMS};
+use serde_json::json;
+
+/// Segments shorter than this are never split off.
+const MIN_SEGMENT_LENGTH: usize = 2;
+
+#[derive(Serialize)]
+pub struct ChangePoint {
+    pub suite: String,
+    pub config_file: String,
+    pub machine: String,
+    pub branch: String,
+    pub metric: String,
+    /// First revision of the new level.
+    pub revision: String,
+    /// Relative change of the mean level, e.g. 0.1 for +10%.
+    pub magnitude: f64,
+    /// One minus the p-value of a Welch test between both segments.
+    pub confidence: f64,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePointQuery {
+    suite: Option<String>,
+    /// Id in the tests table.
+    id: Option<i64>,
+    machine: Option<String>,
+    branch: Option<String>,
+    min_confidence: Option<f64>,
+}
+pub fn api_changepoints_json(
+    db: &Pool<SqliteConnectionManager>,
+    query: ChangePointQuery,
+) -> error::Result<String> {
+    let conn = db.get()?;
+    let test = match query.id {
+        Some(id) => Some(
+            db_test(&conn, id)?
+                .ok_or_else(|| error::Error::NotFound(format!("unknown test id {}", id)))?,
+        ),
+        None => None,
+    };
+    let changepoints = db_changepoints(
+        &conn,
+        query
+            .suite
+            .as_ref()
+            .or_else(|| test.as_ref().map(|t| &t.suite))
+            .map(String::as_str),
+        test.as_ref().map(|t| t.path.as_str()),
+        query.machine.as_deref(),
+        query.branch.as_deref(),
+        query.min_confidence.unwrap_or(0.0),
+    )?;
+    Ok(json!({ "changepoints": changepoints }).to_string())
+}
+
+/// Re-runs detection for every test of every suite.
+pub fn detect_all(
+    conn: &mut Connection,
+    registry: &Registry,
+    settings: &Settings,
+) -> rusqlite::Result<usize> {
+    let mut tests = Vec::new();
+    for suite in registry.suites.iter() {
+        let config_files: Vec<String> = conn
+            .prepare(&format!("SELECT DISTINCT config_file FROM {}", suite.table))?
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+        tests.extend(config_files.into_iter().map(|c| (suite.name.clone(), c)));
+    }
+    detect(conn, registry, settings, &tests)
+}
+
+/// Re-runs detection for the given `(suite, config_file)` pairs on every
+/// machine and branch that ran them and replaces their stored change points
+/// and noise estimates. Returns the number of change points found.
+pub fn detect(
+    conn: &mut Connection,
+    registry: &Registry,
+    settings: &Settings,
+    tests: &[(String, String)],
+) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    let mut found = 0;
+    for (suite_name, config_file) in tests {
+        let suite = match registry.suite(suite_name) {
+            Some(suite) => suite,
+            None => continue,
+        };
+        tx.execute(
+            "DELETE FROM changepoints WHERE suite = ?1 AND config_file = ?2",
+            &[suite_name, config_file],
+        )?;
+        let test_id = db_test_id(&tx, suite_name, config_file)?;
+        if let Some(test_id) = test_id {
+            noise::db_clear(&tx, test_id)?;
+        }
+        for (machine, branch) in db_test_series(&tx, suite, config_file)? {
+            let history = db_revision_history_for_file(
+                &tx,
+                suite,
+                config_file,
+                &machine,
+                &branch,
+                settings.lowest_revision,
+                settings.aggregation,
+            )?;
+            if let Some(test_id) = test_id {
+                noise::db_update(&tx, suite, test_id, &machine, &branch, &history, settings)?;
+            }
+            let changepoints = detect_for_test(suite, config_file, &machine, &branch, &history);
+            for changepoint in changepoints {
+                tx.execute(
+                    concat!(
+                        "INSERT INTO changepoints (suite, config_file, machine, branch, metric, ",
+                        "revision, magnitude, confidence) ",
+                        "VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+                    ),
+                    params![
+                        changepoint.suite,
+                        changepoint.config_file,
+                        changepoint.machine,
+                        changepoint.branch,
+                        changepoint.metric,
+                        changepoint.revision,
+                        changepoint.magnitude,
+                        changepoint.confidence
+                    ],
+                )?;
+                found += 1;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(found)
+}
+
+fn detect_for_test(
+    suite: &Suite,
+    config_file: &str,
+    machine: &str,
+    branch: &str,
+    history: &[RevisionInfos],
+) -> Vec<ChangePoint> {
+    let mut changepoints = Vec::new();
+    for (i, metric) in suite.metrics.iter().enumerate() {
+        let series: Vec<(&str, f64)> = history
+            .iter()
+            .map(|r| (r.revision.as_str(), r.stats[i]))
+            .filter(|(_, v)| v.is_finite())
+            .collect();
+        let values: Vec<f64> = series.iter().map(|(_, v)| *v).collect();
+        let boundaries = pelt(&values);
+
+        let mut segment_starts = vec![0];
+        segment_starts.extend(boundaries.iter().cloned());
+        segment_starts.push(values.len());
+        for w in segment_starts.windows(3) {
+            let before = &values[w[0]..w[1]];
+            let after = &values[w[1]..w[2]];
+            let confidence =
+                stats::welch(before, after, 0.95).map_or(0.0, |welch| 1.0 - welch.p_value);
+            changepoints.push(ChangePoint {
+                suite: suite.name.clone(),
+                config_file: config_file.to_string(),
+                machine: machine.to_string(),
+                branch: branch.to_string(),
+                metric: metric.name.clone(),
+                revision: series[w[1]].0.to_string(),
+                magnitude: stats::mean(after) / stats::mean(before) - 1.0,
+                confidence,
+            });
+        }
+    }
+    changepoints
+}
+
+/// Pruned Exact Linear Time change-point search (Killick et al., 2012) for
+/// changes in mean. Returns the indices at which a new segment starts.
+fn pelt(values: &[f64]) -> Vec<usize> {
+    let n = values.len();
+    if n < 2 * MIN_SEGMENT_LENGTH {
+        return Vec::new();
+    }
+
+    // Noise level from the first differences, which are robust against the
+    // level shifts we are looking for.
+    let sigma = stats::difference_sigma(values);
+    let variance = if sigma > 0.0 {
+        sigma * sigma
+    } else {
+        f64::EPSILON
+    };
+    let penalty = 2.0 * (n as f64).ln();
+
+    let mut sum = vec![0.0; n + 1];
+    let mut sum_sq = vec![0.0; n + 1];
+    for (i, v) in values.iter().enumerate() {
+        sum[i + 1] = sum[i] + v;
+        sum_sq[i + 1] = sum_sq[i] + v * v;
+    }
+    let cost = |s: usize, t: usize| {
+        let len = (t - s) as f64;
+        let seg_sum = sum[t] - sum[s];
+        (sum_sq[t] - sum_sq[s] - seg_sum * seg_sum / len) / variance
+    };
+
+    // best[t]: minimal penalized cost of segmenting values[..t].
+    let mut best = vec![f64::INFINITY; n + 1];
+    let mut last_change = vec![0; n + 1];
+    best[0] = -penalty;
+    let mut candidates = vec![0];
+    for t in MIN_SEGMENT_LENGTH..=n {
+        let (s_best, f_best) = candidates
+            .iter()
+            .map(|&s| (s, best[s] + cost(s, t) + penalty))
+            .fold((0, f64::INFINITY), |a, b| if b.1 < a.1 { b } else { a });
+        best[t] = f_best;
+        last_change[t] = s_best;
+        candidates.retain(|&s| best[s] + cost(s, t) <= f_best);
+        candidates.push(t + 1 - MIN_SEGMENT_LENGTH);
+    }
+
+    let mut boundaries = Vec::new();
+    let mut t = last_change[n];
+    while t > 0 {
+        boundaries.push(t);
+        t = last_change[t];
+    }
+    boundaries.reverse();
+    boundaries
+}
+
+pub fn db_changepoints(
+    conn: &Connection,
+    suite: Option<&str>,
+    config_file: Option<&str>,
+    machine: Option<&str>,
+    branch: Option<&str>,
+    min_confidence: f64,
+) -> rusqlite::Result<Vec<ChangePoint>> {
+    let mut stmt = conn.prepare_cached(concat!(
+        "SELECT c.suite, c.config_file, c.machine, c.branch, c.metric, c.revision, ",
+        "c.magnitude, c.confidence ",
+        "FROM changepoints c LEFT JOIN revisions r ON r.revision = c.revision ",
+        "WHERE (?1 IS NULL OR c.suite = ?1) ",
+        "AND (?2 IS NULL OR c.config_file = ?2) ",
+        "AND (?3 IS NULL OR c.machine = ?3) ",
+        "AND (?4 IS NULL OR c.branch = ?4) ",
+        "AND c.confidence >= ?5 ",
+        "ORDER BY c.suite, c.config_file, c.machine, c.branch, c.metric, r.sequence"
+    ))?;
+    let params: [&dyn ToSql; 5] = [&suite, &config_file, &machine, &branch, &min_confidence];
+    let results = stmt.query_map(&params, |row| {
+        Ok(ChangePoint {
+            suite: row.get(0)?,
+            config_file: row.get(1)?,
+            machine: row.get(2)?,
+            branch: row.get(3)?,
+            metric: row.get(4)?,
+            revision: row.get(5)?,
+            magnitude: row.get(6)?,
+            confidence: row.get(7)?,
+        })
+    })?;
+    Ok(results.filter_map(|r| r.ok()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metric;
+
+    /// Deterministic noise in [-1, 1).
+    fn noise(n: usize) -> Vec<f64> {
+        let mut state: u64 = 12345;
+        (0..n)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+            })
+            .collect()
+    }
+
+    /// `level` up to `step`, `level + shift` from there on, plus noise.
+    fn step(n: usize, step: usize, level: f64, shift: f64) -> Vec<f64> {
+        noise(n)
+            .iter()
+            .enumerate()
+            .map(|(i, e)| if i < step { level } else { level + shift } + e)
+            .collect()
+    }
+
+    #[test]
+    fn finds_a_clean_step() {
+        assert_eq!(
+            pelt(&[1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0, 2.0]),
+            vec![5]
+        );
+        assert_eq!(pelt(&step(30, 15, 100.0, 10.0)), vec![15]);
+        assert_eq!(pelt(&step(100, 50, 100.0, 10.0)), vec![50]);
+    }
+
+    #[test]
+    fn no_change_points_without_a_step() {
+        assert!(pelt(&[5.0; 20]).is_empty());
+        for n in [10, 30, 100].iter() {
+            assert!(pelt(&step(*n, 0, 100.0, 0.0)).is_empty());
+        }
+    }
+
+    #[test]
+    fn short_series_have_no_change_points() {
+        assert!(pelt(&[]).is_empty());
+        let short: Vec<f64> = (0..2 * MIN_SEGMENT_LENGTH - 1)
+            .map(|i| i as f64 * 100.0)
+            .collect();
+        assert!(pelt(&short).is_empty());
+    }
+
+    #[test]
+    fn reports_the_first_revision_of_the_new_level() {
+        let suite = Suite {
+            name: "csb".to_string(),
+            title: "CSB Benchmarks".to_string(),
+            table: "processed_csb".to_string(),
+            metrics: vec![Metric {
+                name: "time".to_string(),
+                column: "player_total_time".to_string(),
+                title: "Run Time".to_string(),
+                unit: "s".to_string(),
+                color: "rgb(255, 205, 86)".to_string(),
+                lower_is_better: true,
+            }],
+        };
+        let mut values = step(20, 10, 100.0, 10.0);
+        // A revision without successful runs is skipped.
+        values[4] = f64::NAN;
+        let history: Vec<RevisionInfos> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| RevisionInfos {
+                revision: (1000 + i).to_string(),
+                sequence: 1000 + i as i64,
+                stats: vec![*v],
+                samples: vec![vec![*v]],
+                failures: Vec::new(),
+            })
+            .collect();
+
+        let changepoints = detect_for_test(&suite, "a.csb", "ci-1", "", &history);
+        assert_eq!(changepoints.len(), 1);
+        let changepoint = &changepoints[0];
+        assert_eq!(changepoint.revision, "1010");
+        assert_eq!(changepoint.metric, "time");
+        assert!((changepoint.magnitude - 0.1).abs() < 0.02);
+        assert!(changepoint.confidence > 0.99);
+    }
+}
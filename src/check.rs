@@ -0,0 +1,305 @@
+use crate::comparison::{
+    classify, db_revision_comparison, describe_failures, failure_messages, format_percent,
+    relative_change, validate_revisions, Settings, Status,
+};
+use crate::metrics::Registry;
+use crate::report::text_table;
+use crate::revisions::revision_label;
+use rusqlite::Connection;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CheckConfig {
+    /// Overrides the per-test thresholds of the comparison for the gate.
+    pub threshold: Option<f64>,
+    /// Tests whose regressions are reported but never fail the check.
+    pub allow: Vec<String>,
+    pub thresholds: Vec<ThresholdOverride>,
+}
+
+/// A threshold for one test, one metric, or one metric of one test.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ThresholdOverride {
+    pub test: Option<String>,
+    pub metric: Option<String>,
+    pub threshold: f64,
+}
+
+impl CheckConfig {
+    /// `default` is the threshold the comparison used for this test.
+    fn threshold(&self, default: f64, test: &str, metric: &str) -> f64 {
+        let matches = |o: &&ThresholdOverride| {
+            o.test.as_ref().is_none_or(|t| t == test)
+                && o.metric.as_ref().is_none_or(|m| m == metric)
+        };
+        // Overrides naming both the test and the metric win over partial ones.
+        self.thresholds
+            .iter()
+            .filter(matches)
+            .max_by_key(|o| o.test.is_some() as u8 * 2 + o.metric.is_some() as u8)
+            .map(|o| o.threshold)
+            .or(self.threshold)
+            .unwrap_or(default)
+    }
+}
+
+/// Compares `base` against `head`, prints all changed tests and returns
+/// whether the check passed, i.e. nothing outside the allowlist regressed
+/// or started failing.
+#[allow(clippy::too_many_arguments)]
+pub fn check(
+    conn: &Connection,
+    registry: &Registry,
+    settings: &Settings,
+    config: &CheckConfig,
+    machine: &str,
+    branch: &str,
+    base: &str,
+    head: &str,
+) -> Result<bool, String> {
+    validate_revisions(conn, registry, machine, branch, &[base, head])?;
+
+    let suites = db_revision_comparison(
+        conn,
+        registry,
+        settings,
+        settings.aggregation,
+        machine,
+        branch,
+        base,
+        head,
+        "change",
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut rows = vec![vec![
+        "STATUS".to_string(),
+        "SUITE".to_string(),
+        "TEST".to_string(),
+        "METRIC".to_string(),
+        revision_label(base),
+        revision_label(head),
+        "CHANGE".to_string(),
+    ]];
+    let mut passed = true;
+    for comparison in suites.iter() {
+        for test in comparison.tests.iter() {
+            let allowed = config.allow.contains(&test.name);
+            if test.failures[0].is_empty() && !test.failures[1].is_empty() {
+                if !allowed {
+                    passed = false;
+                }
+                rows.push(vec![
+                    if allowed { "allowed" } else { "FAILED" }.to_string(),
+                    comparison.suite.name.clone(),
+                    test.name.clone(),
+                    String::new(),
+                    String::new(),
+                    describe_failures(&test.failures[1]),
+                    failure_messages(&test.failures[1]).replace('\n', "; "),
+                ]);
+            }
+            for (metric, m) in comparison.suite.metrics.iter().zip(test.metrics.iter()) {
+                // Without successful runs on both sides there is nothing to
+                // compare; failures are reported above.
+                if m.samples0.is_empty() || m.samples1.is_empty() {
+                    continue;
+                }
+                let threshold = config.threshold(m.threshold, &test.name, &metric.name);
+                let status = classify(
+                    m.value0,
+                    m.value1,
+                    metric.lower_is_better,
+                    threshold,
+                    m.significant,
+                );
+                let status = match status {
+                    Status::Unchanged => continue,
+                    Status::Improvement => "improved",
+                    Status::Regression if allowed => "allowed",
+                    Status::Regression => {
+                        passed = false;
+                        "REGRESSED"
+                    }
+                };
+                rows.push(vec![
+                    status.to_string(),
+                    comparison.suite.name.clone(),
+                    test.name.clone(),
+                    metric.name.clone(),
+                    metric.format(m.value0),
+                    metric.format(m.value1),
+                    match &m.welch {
+                        Some(w) => format!(
+                            "{} [{}, {}]",
+                            relative_change(m.value0, m.value1),
+                            format_percent(w.relative_change_ci.0),
+                            format_percent(w.relative_change_ci.1)
+                        ),
+                        None => relative_change(m.value0, m.value1),
+                    },
+                ]);
+            }
+        }
+        // New and dropped tests can't regress, but should not go unnoticed.
+        for (status, tests) in [
+            ("added", &comparison.added),
+            ("removed", &comparison.removed),
+        ]
+        .iter()
+        {
+            for test in tests.iter() {
+                rows.push(vec![
+                    status.to_string(),
+                    comparison.suite.name.clone(),
+                    test.name.clone(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                ]);
+            }
+        }
+    }
+
+    if rows.len() == 1 {
+        println!("no significant changes");
+    } else {
+        print!("{}", text_table(&rows));
+    }
+    Ok(passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::{Metric, Suite};
+    use crate::revisions::{self, Commit};
+    use crate::schema;
+    use crate::testcases;
+    use rusqlite::params;
+
+    fn registry() -> Registry {
+        Registry::new(
+            vec![Suite {
+                name: "csb".to_string(),
+                title: "CSB Benchmarks".to_string(),
+                table: "processed_csb".to_string(),
+                metrics: vec![Metric {
+                    name: "time".to_string(),
+                    column: "player_total_time".to_string(),
+                    title: "Run Time".to_string(),
+                    unit: "s".to_string(),
+                    color: "rgb(255, 205, 86)".to_string(),
+                    lower_is_better: true,
+                }],
+            }],
+            Vec::new(),
+        )
+        .unwrap()
+    }
+
+    /// Five runs of `a.csb` per revision, the second one `ratio` times slower.
+    fn database(ratio: f64) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        schema::migrate(&mut conn).unwrap();
+        schema::ensure_suites(&conn, &registry()).unwrap();
+        testcases::db_register(&conn, &registry(), "csb", "a.csb").unwrap();
+        for revision in ["812000", "812001"].iter() {
+            revisions::db_record_revision(
+                &conn,
+                revision,
+                "",
+                &Commit::default(),
+                Settings::default().lowest_revision,
+            )
+            .unwrap();
+        }
+        for time in [1.0, 1.01, 0.99, 1.0, 1.02].iter() {
+            for (revision, time) in [("812000", *time), ("812001", time * ratio)].iter() {
+                conn.execute(
+                    "INSERT INTO processed_csb (revision, config_file, player_total_time) VALUES (?1, 'a.csb', ?2)",
+                    params![revision, time],
+                )
+                .unwrap();
+            }
+        }
+        conn
+    }
+
+    fn run(conn: &Connection, config: &CheckConfig) -> bool {
+        check(
+            conn,
+            &registry(),
+            &Settings::default(),
+            config,
+            "",
+            "",
+            "812000",
+            "812001",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn passes_without_regressions() {
+        assert!(run(&database(1.0), &CheckConfig::default()));
+        assert!(run(&database(0.8), &CheckConfig::default()));
+    }
+
+    #[test]
+    fn fails_on_regressions() {
+        assert!(!run(&database(1.2), &CheckConfig::default()));
+    }
+
+    #[test]
+    fn tests_failing_on_both_sides_pass() {
+        let conn = database(1.0);
+        testcases::db_register(&conn, &registry(), "csb", "b.csb").unwrap();
+        for revision in ["812000", "812001"].iter() {
+            conn.execute(
+                "INSERT INTO processed_csb (revision, config_file, status) VALUES (?1, 'b.csb', 'crashed')",
+                params![revision],
+            )
+            .unwrap();
+        }
+        assert!(run(&conn, &CheckConfig::default()));
+    }
+
+    #[test]
+    fn allowed_tests_and_thresholds_pass() {
+        let allowed = CheckConfig {
+            allow: vec!["a.csb".to_string()],
+            ..CheckConfig::default()
+        };
+        assert!(run(&database(1.2), &allowed));
+        let lenient = CheckConfig {
+            threshold: Some(0.5),
+            ..CheckConfig::default()
+        };
+        assert!(run(&database(1.2), &lenient));
+    }
+
+    #[test]
+    fn most_specific_threshold_wins() {
+        let o = |test: Option<&str>, metric: Option<&str>, threshold| ThresholdOverride {
+            test: test.map(str::to_string),
+            metric: metric.map(str::to_string),
+            threshold,
+        };
+        let config = CheckConfig {
+            threshold: Some(0.1),
+            allow: Vec::new(),
+            thresholds: vec![
+                o(Some("a.csb"), Some("time"), 0.3),
+                o(None, Some("time"), 0.2),
+                o(Some("a.csb"), None, 0.25),
+            ],
+        };
+        assert_eq!(config.threshold(0.05, "a.csb", "time"), 0.3);
+        assert_eq!(config.threshold(0.05, "a.csb", "mem"), 0.25);
+        assert_eq!(config.threshold(0.05, "b.csb", "time"), 0.2);
+        assert_eq!(config.threshold(0.05, "b.csb", "mem"), 0.1);
+        assert_eq!(CheckConfig::default().threshold(0.05, "b.csb", "mem"), 0.05);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::branches::MAIN_LINE;
+use crate::revisions::{self, Commit};
+use itertools::Itertools;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, ToSql};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Commits are looked up in chunks to stay below SQLite's parameter limit.
+const LOOKUP_CHUNK_SIZE: usize = 500;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CommitSettings {
+    /// Link to a commit, `{revision}` is replaced by the revision, e.g.
+    /// `https://github.com/org/repo/commit/{revision}`.
+    pub url: Option<String>,
+}
+
+impl CommitSettings {
+    pub fn link(&self, revision: &str) -> Option<String> {
+        self.url
+            .as_ref()
+            .map(|url| url.replace("{revision}", revision))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CommitMetadata {
+    pub revision: String,
+    pub author: String,
+    pub date: String,
+    pub message: String,
+}
+
+impl CommitMetadata {
+    /// First line of the message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+}
+
+struct LogEntry {
+    metadata: CommitMetadata,
+    /// `None` if the log doesn't tell, in which case the previous entry of
+    /// the log is taken as parent for ordering.
+    parents: Option<Vec<String>>,
+    /// The revision number, for SVN.
+    sequence: Option<i64>,
+}
+
+/// Reads `svn log --xml` or `git log [--parents]` output from `path`, or from
+/// stdin if `path` is `-`.
+pub fn import_log(
+    db: &Pool<SqliteConnectionManager>,
+    lowest_revision: i64,
+    path: &str,
+) -> Result<(), String> {
+    let mut content = String::new();
+    if path == "-" {
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|e| format!("stdin: {}", e))?;
+    } else {
+        content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    let trimmed = content.trim_start();
+    let entries = if trimmed.starts_with('<') {
+        parse_svn_log(trimmed)?
+    } else if trimmed.starts_with("commit ") {
+        parse_git_log(trimmed)
+    } else {
+        return Err(format!(
+            "{}: expected `svn log --xml` or `git log` output",
+            path
+        ));
+    };
+
+    let mut conn = db.get().map_err(|e| e.to_string())?;
+    let imported =
+        db_insert_log(&mut conn, &entries, lowest_revision).map_err(|e| e.to_string())?;
+    println!("imported {} commits", imported);
+    Ok(())
+}
+
+/// Expects entries oldest first.
+fn db_insert_log(
+    conn: &mut Connection,
+    entries: &[LogEntry],
+    lowest_revision: i64,
+) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    let mut previous: Option<&str> = None;
+    for entry in entries {
+        let metadata = &entry.metadata;
+        let parents = match &entry.parents {
+            Some(parents) => parents.clone(),
+            None => previous.map(|p| vec![p.to_string()]).unwrap_or_default(),
+        };
+        revisions::db_record_revision(
+            &tx,
+            &metadata.revision,
+            MAIN_LINE,
+            &Commit {
+                sequence: entry.sequence,
+                parents,
+                ..Commit::default()
+            },
+            lowest_revision,
+        )?;
+        tx.execute(
+            concat!(
+                "INSERT OR REPLACE INTO commits (revision, author, date, message) ",
+                "VALUES (?1, ?2, ?3, ?4)"
+            ),
+            params![
+                metadata.revision,
+                metadata.author,
+                metadata.date,
+                metadata.message
+            ],
+        )?;
+        previous = Some(metadata.revision.as_str());
+    }
+    tx.commit()?;
+    Ok(entries.len())
+}
+
+fn parse_svn_log(xml: &str) -> Result<Vec<LogEntry>, String> {
+    let document = roxmltree::Document::parse(xml).map_err(|e| e.to_string())?;
+    let mut entries = Vec::new();
+    for node in document
+        .descendants()
+        .filter(|n| n.has_tag_name("logentry"))
+    {
+        let revision = node
+            .attribute("revision")
+            .ok_or("logentry without revision")?;
+        let text = |tag: &str| {
+            node.children()
+                .find(|n| n.has_tag_name(tag))
+                .and_then(|n| n.text())
+                .unwrap_or("")
+                .trim()
+                .to_string()
+        };
+        entries.push(LogEntry {
+            metadata: CommitMetadata {
+                revision: revision.to_string(),
+                author: text("author"),
+                date: text("date"),
+                message: text("msg"),
+            },
+            parents: None,
+            sequence: revision.parse().ok(),
+        });
+    }
+    // `svn log` lists the newest revision first unless asked otherwise.
+    entries.sort_by_key(|e| e.metadata.revision.parse::<u64>().unwrap_or(0));
+    Ok(entries)
+}
+
+fn parse_git_log(log: &str) -> Vec<LogEntry> {
+    let mut entries = Vec::new();
+    let mut current: Option<LogEntry> = None;
+    let mut message: Vec<&str> = Vec::new();
+    for line in log.lines() {
+        if let Some(rest) = line.strip_prefix("commit ") {
+            if let Some(mut entry) = current.take() {
+                entry.metadata.message = message.join("\n").trim().to_string();
+                entries.push(entry);
+            }
+            message.clear();
+            // "commit <hash> [<parent>...] [(<refs>)]"
+            let hashes: Vec<&str> = rest
+                .split('(')
+                .next()
+                .unwrap_or("")
+                .split_whitespace()
+                .collect();
+            current = Some(LogEntry {
+                metadata: CommitMetadata {
+                    revision: hashes.first().unwrap_or(&"").to_string(),
+                    author: String::new(),
+                    date: String::new(),
+                    message: String::new(),
+                },
+                parents: if hashes.len() > 1 {
+                    Some(hashes[1..].iter().map(|h| h.to_string()).collect())
+                } else {
+                    None
+                },
+                sequence: None,
+            });
+        } else if let Some(entry) = current.as_mut() {
+            if let Some(author) = line.strip_prefix("Author:") {
+                entry.metadata.author = author.trim().to_string();
+            } else if let Some(date) = line.strip_prefix("Date:") {
+                entry.metadata.date = date.trim().to_string();
+            } else if let Some(text) = line.strip_prefix("    ") {
+                message.push(text);
+            } else if line.is_empty() && !message.is_empty() {
+                message.push("");
+            }
+        }
+    }
+    if let Some(mut entry) = current {
+        entry.metadata.message = message.join("\n").trim().to_string();
+        entries.push(entry);
+    }
+    // `git log` lists the newest commit first.
+    entries.reverse();
+    entries
+}
+
+/// Metadata of the given revisions, as far as it has been imported.
+pub fn db_commits<S: AsRef<str>>(
+    conn: &Connection,
+    revisions: &[S],
+) -> rusqlite::Result<HashMap<String, CommitMetadata>> {
+    let mut commits = HashMap::new();
+    for chunk in revisions.chunks(LOOKUP_CHUNK_SIZE) {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT revision, author, date, message FROM commits WHERE revision IN ({})",
+            (1..=chunk.len()).map(|i| format!("?{}", i)).join(", ")
+        ))?;
+        let names: Vec<&str> = chunk.iter().map(AsRef::as_ref).collect();
+        let params: Vec<&dyn ToSql> = names.iter().map(|n| n as &dyn ToSql).collect();
+        let rows = stmt.query_map(&params, commit_from_row)?;
+        for commit in rows.filter_map(|r| r.ok()) {
+            commits.insert(commit.revision.clone(), commit);
+        }
+    }
+    Ok(commits)
+}
+
+/// Commits after `low` up to and including `high`, newest first. These are
+/// the suspects for a change between both revisions.
+pub fn db_commits_between(
+    conn: &Connection,
+    low: &str,
+    high: &str,
+) -> rusqlite::Result<Vec<CommitMetadata>> {
+    let mut stmt = conn.prepare_cached(concat!(
+        "SELECT c.revision, c.author, c.date, c.message ",
+        "FROM commits c JOIN revisions r ON r.revision = c.revision ",
+        "WHERE r.sequence > (SELECT sequence FROM revisions WHERE revision = ?1) ",
+        "AND r.sequence <= (SELECT sequence FROM revisions WHERE revision = ?2) ",
+        "ORDER BY r.sequence DESC"
+    ))?;
+    let commits = stmt
+        .query_map(&[&low, &high], commit_from_row)?
+        .filter_map(|r| r.ok())
+        .collect();
+    Ok(commits)
+}
+
+fn commit_from_row(row: &rusqlite::Row) -> rusqlite::Result<CommitMetadata> {
+    Ok(CommitMetadata {
+        revision: row.get(0)?,
+        author: row.get(1)?,
+        date: row.get(2)?,
+        message: row.get(3)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparison::Settings;
+    use crate::schema;
+
+    const GIT_LOG: &str = "\
+commit 3333333333333333333333333333333333333333 2222222222222222222222222222222222222222 (HEAD -> main)
+Author: Jane Doe <jane@example.com>
+Date:   Tue Mar 3 10:00:00 2020 +0100
+
+    Speed up cutting
+
+    Caches the bounding boxes.
+
+commit 2222222222222222222222222222222222222222 1111111111111111111111111111111111111111
+Author: John Doe <john@example.com>
+Date:   Mon Mar 2 10:00:00 2020 +0100
+
+    Fix a leak
+";
+
+    const SVN_LOG: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<log>
+<logentry revision="812346">
+<author>jane</author>
+<date>2020-03-03T09:00:00.000000Z</date>
+<msg>Speed up cutting</msg>
+</logentry>
+<logentry revision="812345">
+<author>john</author>
+<date>2020-03-02T09:00:00.000000Z</date>
+<msg>Fix a leak
+</msg>
+</logentry>
+</log>"#;
+
+    #[test]
+    fn parses_git_logs_oldest_first() {
+        let entries = parse_git_log(GIT_LOG);
+        assert_eq!(entries.len(), 2);
+        let fix = &entries[0].metadata;
+        assert_eq!(fix.revision, "2222222222222222222222222222222222222222");
+        assert_eq!(fix.author, "John Doe <john@example.com>");
+        assert_eq!(fix.date, "Mon Mar 2 10:00:00 2020 +0100");
+        assert_eq!(fix.message, "Fix a leak");
+        assert_eq!(
+            entries[1].parents,
+            Some(vec!["2222222222222222222222222222222222222222".to_string()])
+        );
+        let speedup = &entries[1].metadata;
+        assert_eq!(
+            speedup.message,
+            "Speed up cutting\n\nCaches the bounding boxes."
+        );
+        assert_eq!(speedup.subject(), "Speed up cutting");
+    }
+
+    #[test]
+    fn git_logs_without_parents_leave_them_open() {
+        let entries = parse_git_log("commit abc\nAuthor: Jane\n\n    Initial\n");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].metadata.revision, "abc");
+        assert_eq!(entries[0].parents, None);
+    }
+
+    #[test]
+    fn parses_svn_logs_oldest_first() {
+        let entries = parse_svn_log(SVN_LOG).unwrap();
+        let revisions: Vec<&str> = entries
+            .iter()
+            .map(|e| e.metadata.revision.as_str())
+            .collect();
+        assert_eq!(revisions, vec!["812345", "812346"]);
+        assert_eq!(entries[0].metadata.author, "john");
+        assert_eq!(entries[0].metadata.message, "Fix a leak");
+        assert!(parse_svn_log("<log><logentry/></log>")
+            .err()
+            .unwrap()
+            .contains("without revision"));
+    }
+
+    #[test]
+    fn imported_commits_are_ordered() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        schema::migrate(&mut conn).unwrap();
+        let entries = parse_git_log("commit aaa\n\n    First\n\ncommit bbb\n\n    Second\n");
+        assert_eq!(
+            db_insert_log(&mut conn, &entries, Settings::default().lowest_revision),
+            Ok(2)
+        );
+        // Listed newest first, so `bbb` is the parent of `aaa`.
+        let between = db_commits_between(&conn, "bbb", "aaa").unwrap();
+        assert_eq!(between.len(), 1);
+        assert_eq!(between[0].message, "First");
+        assert_eq!(db_commits(&conn, &["bbb", "ccc"]).unwrap().len(), 1);
+    }
+}
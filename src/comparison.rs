@@ -1,39 +1,122 @@
-use crate::LOWEST_REVISION;
+use crate::aliases::db_aliases;
+use crate::branches::{branch_label, db_branches, db_merge_base, MAIN_LINE};
+use crate::commits::{db_commits, db_commits_between, CommitMetadata, CommitSettings};
+use crate::error;
+use crate::ingest::{Failure, RunStatus};
+use crate::machines::{db_has_runs, db_machines};
+use crate::metrics::{Metric, Registry, Suite};
+use crate::noise::db_thresholds;
+use crate::revisions::{db_revision_known, revision_label};
+use crate::stats::{self, Aggregation};
+use crate::test_detail::test_url;
+use crate::testcases::db_suite_tests;
+use itertools::Itertools;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{Connection, NO_PARAMS};
+use rusqlite::{params, Connection};
+use serde_json::json;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Extract)]
+/// Without `r1`, a comparison spans this many revisions before the newest.
+const DEFAULT_RANGE: usize = 4;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Relative change beyond which a significant difference is highlighted,
+    /// for tests without a noise estimate.
+    pub threshold: f64,
+    /// Otherwise the threshold is this multiple of the test's relative
+    /// noise, but at least `min_threshold`.
+    pub noise_multiplier: f64,
+    pub min_threshold: f64,
+    /// Number of recent revisions the noise is estimated from.
+    pub noise_window: usize,
+    /// Largest p-value at which a difference counts as significant.
+    pub significance_level: f64,
+    /// Used when a request does not ask for a specific aggregation.
+    pub aggregation: Aggregation,
+    /// Revisions with a lower sequence number in the revisions table are
+    /// hidden. Also where the sequence of a history without SVN revision
+    /// numbers starts.
+    pub lowest_revision: i64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            threshold: 0.05,
+            noise_multiplier: 3.0,
+            min_threshold: 0.005,
+            noise_window: 30,
+            significance_level: 0.05,
+            aggregation: Aggregation::Mean,
+            lowest_revision: 800_000,
+        }
+    }
+}
+
+#[derive(Deserialize)]
 pub struct IndexQuery {
-    r1: Option<u32>,
-    r2: Option<u32>,
+    r1: Option<String>,
+    r2: Option<String>,
     sort: Option<String>,
+    agg: Option<String>,
+    machine: Option<String>,
+    /// Machine of the r2 side, if it differs from `machine`.
+    machine2: Option<String>,
+    /// Defaults to the main line.
+    branch: Option<String>,
+    /// Branch of the r2 side, if it differs from `branch`.
+    branch2: Option<String>,
 }
 pub fn index(
     db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
+    commit_settings: &CommitSettings,
     args: IndexQuery,
-) -> Result<String, tower_web::Error> {
-    let conn = db.get().unwrap();
-    let revisions = db_all_revisions(&conn, "processed_csb").map_err(|e| {
-        tower_web::Error::new(
-            "SQL Error",
-            &e.to_string(),
-            http::StatusCode::INTERNAL_SERVER_ERROR,
-        )
-    })?;
-
-    let revision_low = args.r1.unwrap_or_else(|| revisions[revisions.len() - 5]);
-    let revision_high = args.r2.unwrap_or_else(|| *revisions.last().unwrap());
-    let sort = args.sort.unwrap_or_else(|| "cut time".to_string());
-
-    let (csb_tests, ini_tests) = db_revision_comparison(&conn, revision_low, revision_high, &sort)
-        .map_err(|e| {
-            tower_web::Error::new(
-                "SQL Error",
-                &e.to_string(),
-                http::StatusCode::INTERNAL_SERVER_ERROR,
-            )
-        })?;
+) -> error::Result<String> {
+    let conn = db.get()?;
+    let Comparison {
+        revisions,
+        revision_low,
+        revision_high,
+        sort,
+        aggregation,
+        machine,
+        machine2,
+        machines,
+        branch,
+        branch2,
+        branches,
+        suites,
+    } = load_comparison(&conn, registry, settings, args)?;
+    let (label_low, label_high) = side_labels(
+        (&machine, &branch, &revision_low),
+        (&machine2, &branch2, &revision_high),
+    );
+    let commits = db_commits_between(&conn, &revision_low, &revision_high)?;
+    let subjects = db_commits(&conn, &revisions)?
+        .into_iter()
+        .map(|(revision, commit)| (revision, truncate(commit.subject(), SUBJECT_LENGTH)))
+        .collect();
+
+    let mut sort_options = vec!["change", "name"];
+    sort_options.extend(registry.metric_titles());
+    let graphs: Vec<_> = registry
+        .suites
+        .iter()
+        .flat_map(|suite| {
+            suite.metrics.iter().map(move |metric| {
+                json!({
+                    "id": format!("{}_{}", suite.name, metric.name),
+                    "title": format!("{} {}", suite.name.to_uppercase(), metric.title),
+                })
+            })
+        })
+        .collect();
 
     Ok(Index {
         page: &Page {
@@ -41,24 +124,211 @@ pub fn index(
             revision_low,
             revision_high,
             sort,
-            csb_tests,
-            ini_tests,
+            sort_options,
+            aggregation,
+            machine,
+            machine2,
+            machines,
+            branch,
+            branch2,
+            branches,
+            label_low,
+            label_high,
+            commits,
+            subjects,
+            commit_settings,
+            suites,
+            graphs: json!(graphs).to_string(),
         },
     }
     .to_string())
 }
 
-pub struct Page {
-    revisions: Vec<u32>,
-    revision_low: u32,
-    revision_high: u32,
+pub fn api_compare_json(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
+    args: IndexQuery,
+) -> error::Result<String> {
+    let conn = db.get()?;
+    let comparison = load_comparison(&conn, registry, settings, args)?;
+    let suites: Vec<_> = comparison.suites.iter().map(|s| s.to_json()).collect();
+    Ok(json!({
+        "r1": comparison.revision_low,
+        "r2": comparison.revision_high,
+        "sort": comparison.sort,
+        "aggregation": comparison.aggregation.name(),
+        "machine": comparison.machine,
+        "machine2": comparison.machine2,
+        "branch": comparison.branch,
+        "branch2": comparison.branch2,
+        "suites": suites,
+    })
+    .to_string())
+}
+
+pub struct Comparison<'a> {
+    /// Ordered by the revisions table, oldest first.
+    pub revisions: Vec<String>,
+    pub revision_low: String,
+    pub revision_high: String,
+    pub sort: String,
+    pub aggregation: Aggregation,
+    /// Machine of the `revision_low` side.
+    pub machine: String,
+    /// Machine of the `revision_high` side. Differs from `machine` when
+    /// comparing two machines, usually at the same revision.
+    pub machine2: String,
+    pub machines: Vec<String>,
+    /// Branch of the `revision_low` side.
+    pub branch: String,
+    /// Branch of the `revision_high` side. Differs from `branch` when
+    /// comparing a branch head with its merge base.
+    pub branch2: String,
+    pub branches: Vec<String>,
+    pub suites: Vec<SuiteComparison<'a>>,
+}
+
+/// Column labels for both `(machine, branch, revision)` sides, made of the
+/// parts that differ. The revisions are shown if nothing else differs.
+pub fn side_labels(side1: (&str, &str, &str), side2: (&str, &str, &str)) -> (String, String) {
+    let label = |(machine, branch, revision): (&str, &str, &str)| {
+        let mut parts = Vec::new();
+        if side1.0 != side2.0 {
+            parts.push(machine.to_string());
+        }
+        if side1.1 != side2.1 {
+            parts.push(branch_label(branch).to_string());
+        }
+        if side1.2 != side2.2 || parts.is_empty() {
+            parts.push(revision_label(revision));
+        }
+        parts.join(" ")
+    };
+    (label(side1), label(side2))
+}
+
+/// Resolves the query defaults and compares both sides. With `machine2`,
+/// `r1` defaults to `r2` so that both machines are compared at one revision.
+/// With a `branch2` other than the main line, `r1` defaults to the merge
+/// base of `r2`. Otherwise `r1` defaults to a few revisions before `r2`.
+pub fn load_comparison<'a>(
+    conn: &Connection,
+    registry: &'a Registry,
+    settings: &Settings,
+    args: IndexQuery,
+) -> error::Result<Comparison<'a>> {
+    let machines = db_machines(conn, registry)?;
+    let machine = args
+        .machine
+        .or_else(|| machines.first().cloned())
+        .unwrap_or_default();
+    let machine2 = args.machine2.unwrap_or_else(|| machine.clone());
+    let branch = args.branch.unwrap_or_else(|| MAIN_LINE.to_string());
+    let branch2 = args.branch2.unwrap_or_else(|| branch.clone());
+    let revisions = db_all_revisions(
+        conn,
+        registry,
+        settings.lowest_revision,
+        [(&machine, &branch), (&machine2, &branch2)],
+    )?;
+    let side2 = (machine2.as_str(), branch2.as_str());
+    let heads = db_all_revisions(conn, registry, settings.lowest_revision, [side2, side2])?;
+    let branches = db_branches(conn, registry)?;
+
+    let revision_high = match args.r2 {
+        Some(r2) => r2,
+        None => heads.last().cloned().ok_or_else(|| {
+            error::Error::NotFound(format!(
+                "no runs of machine '{}' on {}",
+                machine2,
+                branch_label(&branch2)
+            ))
+        })?,
+    };
+    let revision_low = match args.r1 {
+        Some(r1) => r1,
+        None if branch2 != branch => {
+            db_merge_base(conn, &branch2, &revision_high)?.ok_or_else(|| {
+                error::Error::NotFound(format!(
+                    "no merge base of {} at {}",
+                    branch_label(&branch2),
+                    revision_label(&revision_high)
+                ))
+            })?
+        }
+        None if machine2 != machine => revision_high.clone(),
+        None => revisions
+            .get(revisions.len().saturating_sub(DEFAULT_RANGE + 1))
+            .cloned()
+            .unwrap_or_else(|| revision_high.clone()),
+    };
+    for revision in [&revision_low, &revision_high].iter() {
+        if !db_revision_known(conn, revision)? {
+            return Err(error::Error::NotFound(format!(
+                "unknown revision {}",
+                revision
+            )));
+        }
+    }
+    let sort = args.sort.unwrap_or_else(|| "change".to_string());
+    let aggregation = Aggregation::from_arg(args.agg.as_deref(), settings.aggregation)
+        .map_err(error::Error::BadRequest)?;
+
+    let suites = db_comparison(
+        conn,
+        registry,
+        settings,
+        aggregation,
+        [
+            (&machine, &branch, &revision_low),
+            (&machine2, &branch2, &revision_high),
+        ],
+        &sort,
+    )?;
+
+    Ok(Comparison {
+        revisions,
+        revision_low,
+        revision_high,
+        sort,
+        aggregation,
+        machine,
+        machine2,
+        machines,
+        branch,
+        branch2,
+        branches,
+        suites,
+    })
+}
+
+pub struct Page<'a> {
+    revisions: Vec<String>,
+    revision_low: String,
+    revision_high: String,
     sort: String,
-    csb_tests: Vec<CsbTest>,
-    ini_tests: Vec<IniTest>,
+    sort_options: Vec<&'a str>,
+    aggregation: Aggregation,
+    machine: String,
+    machine2: String,
+    machines: Vec<String>,
+    branch: String,
+    branch2: String,
+    branches: Vec<String>,
+    label_low: String,
+    label_high: String,
+    /// Commits after `revision_low` up to `revision_high`, newest first.
+    commits: Vec<CommitMetadata>,
+    /// Shortened commit subjects by revision, for the revision selects.
+    subjects: HashMap<String, String>,
+    commit_settings: &'a CommitSettings,
+    suites: Vec<SuiteComparison<'a>>,
+    graphs: String,
 }
 
 markup::define! {
-    Index<'a>(page: &'a Page) {
+    Index<'a>(page: &'a Page<'a>) {
         {markup::doctype()}
         html {
             head {
@@ -68,147 +338,250 @@ markup::define! {
                 link[rel="stylesheet", href="static/style.css"] {}
                 link[rel="icon", type="image/png", href="static/icon.png"] {}
             }
-            body {
+            body[
+                "data-aggregation" = page.aggregation.name(),
+                // Graphs show the history leading to the second revision.
+                "data-machine" = &page.machine2,
+                "data-branch" = &page.branch2
+            ] {
                 {Form { page }}
-                div#summary_charts {
+                div#summary_charts["data-graphs" = &page.graphs] {
                     button[
-                        onclick = format!("loadSummaryCharts({},{})",
-                            page.revision_low, page.revision_high)
+                        onclick = "loadSummaryCharts(this.dataset.r1, this.dataset.r2)",
+                        "data-r1" = &page.revision_low,
+                        "data-r2" = &page.revision_high
                     ] {
                         "Load Summary Charts"
                     }
                 }
-                h1 {"r" {{page.revision_low}} " vs r" {{page.revision_high}}}
-                {CsbTable { page }}
-                {IniTable { page }}
+                h1 {
+                    {&page.label_low} " vs " {&page.label_high}
+                    @if page.revision_low == page.revision_high
+                        && (page.machine != page.machine2 || page.branch != page.branch2) {
+                        " at " {revision_label(&page.revision_low)}
+                    }
+                }
+                @if page.branch2 != MAIN_LINE && page.branch != MAIN_LINE {
+                    {MergeBaseForm { page }}
+                }
+                @if !page.commits.is_empty() {
+                    {Commits { page }}
+                }
+                @for comparison in page.suites.iter() {
+                    {SuiteTable { page, comparison }}
+                }
             }
         }
     }
 
-    Form<'a>(page: &'a Page) {
+    Form<'a>(page: &'a Page<'a>) {
         form {
             "Revision range: "
             select[name="r1"] {
-                {Revisions {page, selected_revision: page.revision_low}}
+                {Revisions {page, selected_revision: &page.revision_low}}
             }
             " "
             select[name="r2"] {
-                {Revisions {page, selected_revision: page.revision_high}}
+                {Revisions {page, selected_revision: &page.revision_high}}
             }
             " Sort by: "
             select[name="sort"] {
-                option[selected? = page.sort == "name"] { "name" }
-                option[selected? = page.sort == "cut time"] { "cut time" }
-                option[selected? = page.sort == "draw time"] { "draw time" }
-                option[selected? = page.sort == "memory"] { "memory" }
+                @for o in page.sort_options.iter() {
+                    option[selected? = page.sort == *o] { {o} }
+                }
+            }
+            " Aggregate: "
+            select[name="agg"] {
+                @for a in Aggregation::ALL.iter() {
+                    option[selected? = *a == page.aggregation] { {a.name()} }
+                }
+            }
+            @if page.machines.len() > 1 {
+                " Machine: "
+                select[name="machine"] {
+                    @for m in page.machines.iter() {
+                        option[selected? = *m == page.machine] { {m} }
+                    }
+                }
+                " vs "
+                select[name="machine2"] {
+                    @for m in page.machines.iter() {
+                        option[selected? = *m == page.machine2] { {m} }
+                    }
+                }
+            }
+            @if page.branches.len() > 1 {
+                " Branch: "
+                select[name="branch"] {
+                    @for b in page.branches.iter() {
+                        option[value = b, selected? = *b == page.branch] { {branch_label(b)} }
+                    }
+                }
+                " vs "
+                select[name="branch2"] {
+                    @for b in page.branches.iter() {
+                        option[value = b, selected? = *b == page.branch2] { {branch_label(b)} }
+                    }
+                }
             }
             " "
             input[type="submit", value="Ok"] {}
         }
     }
 
-    Revisions<'a>(page: &'a Page, selected_revision: u32) {
-        @for r in page.revisions.iter() {
-            option[selected?=r == selected_revision] {{r}}
+    MergeBaseForm<'a>(page: &'a Page<'a>) {
+        form {
+            input[type="hidden", name="machine", value = &page.machine] {}
+            input[type="hidden", name="machine2", value = &page.machine2] {}
+            input[type="hidden", name="branch", value = MAIN_LINE] {}
+            input[type="hidden", name="branch2", value = &page.branch2] {}
+            input[type="hidden", name="r2", value = &page.revision_high] {}
+            input[type="hidden", name="sort", value = &page.sort] {}
+            input[type="hidden", name="agg", value = page.aggregation.name()] {}
+            input[
+                type="submit",
+                value = format!("Compare {} with its merge base", branch_label(&page.branch2))
+            ] {}
         }
     }
 
-    CsbTable<'a>(page: &'a Page) {
-        h2 { "CSB Benchmarks" }
-        table.benchtable {
-            tbody {
-                @for test in page.csb_tests.iter() {
-                    {CsbRow { page, test } }
+    Revisions<'a>(page: &'a Page<'a>, selected_revision: &'a str) {
+        @for r in page.revisions.iter() {
+            option[value = r, selected? = r == *selected_revision] {
+                {revision_label(r)}
+                @if let Some(subject) = page.subjects.get(r) {
+                    " " {subject}
                 }
             }
         }
     }
 
-    CsbRow<'a>(page: &'a Page, test: &'a CsbTest) {
-        tr["data-field-start" = true] {
-            th["data-js-name" = &test.name] {
-                details."toggle-table" {
-                    summary { {test.name} }
-                }
-            }
-            td {
-                "time: "
-                span[style = to_style(test.time0, test.time1)] {
-                    {relative_change(test.time0, test.time1)}
+    Commits<'a>(page: &'a Page<'a>) {
+        details {
+            summary { {page.commits.len()} " commits in range" }
+            table.commits {
+                tbody {
+                    @for c in page.commits.iter() {
+                        tr {
+                            td {
+                                @if let Some(url) = page.commit_settings.link(&c.revision) {
+                                    a[href = url] { {revision_label(&c.revision)} }
+                                } else {
+                                    {revision_label(&c.revision)}
+                                }
+                            }
+                            td { {&c.author} }
+                            td { {&c.date} }
+                            td[title = &c.message] { {c.subject()} }
+                        }
+                    }
                 }
             }
-            td {
-                "mem: "
-                span[style = to_style(test.memory0, test.memory1)] {
-                    {relative_change(test.memory0, test.memory1)}
+        }
+    }
+
+    SuiteTable<'a>(page: &'a Page<'a>, comparison: &'a SuiteComparison<'a>) {
+        h2 { {comparison.suite.title} }
+        table.benchtable {
+            tbody {
+                @for test in comparison.tests.iter() {
+                    {SuiteRow { page, suite: comparison.suite, test } }
                 }
             }
         }
-        tr[style = "display:none"] {
-            th[style = "text-align:right"] { "r" {page.revision_low} }
-            td { {format_time(test.time0)} }
-            td { {format_mem(test.memory0)} }
+        @if !comparison.added.is_empty() {
+            h3 { "Only in " {&page.label_high} }
+            {OneSidedTable {
+                suite: comparison.suite,
+                tests: &comparison.added,
+                machine: &page.machine2,
+                branch: &page.branch2
+            }}
         }
-        tr[style = "display:none"] {
-            th[style = "text-align:right"] { "r" {page.revision_high} }
-            td { {format_time(test.time1)} }
-            td { {format_mem(test.memory1)} }
-        }
-        tr[style = "display:none"] {
-            td[colspan = 3, class="chart", "data-chart-id" = &test.name] {}
+        @if !comparison.removed.is_empty() {
+            h3 { "Only in " {&page.label_low} }
+            {OneSidedTable {
+                suite: comparison.suite,
+                tests: &comparison.removed,
+                machine: &page.machine,
+                branch: &page.branch
+            }}
         }
     }
 
-    IniTable<'a>(page: &'a Page) {
-        h2 { "CSB Benchmarks" }
+    OneSidedTable<'a>(
+        suite: &'a Suite,
+        tests: &'a [OneSidedTest],
+        machine: &'a str,
+        branch: &'a str
+    ) {
         table.benchtable {
             tbody {
-                @for test in page.ini_tests.iter() {
-                    {IniRow { page, test } }
+                @for test in tests.iter() {
+                    tr {
+                        th { a[href = test_url(test.id, machine, branch)] { {test.name} } }
+                        @for (metric, v) in suite.metrics.iter().zip(test.values.iter()) {
+                            td { {metric.name} ": " {format_value(metric, *v)} }
+                        }
+                        {Failures { failures: &test.failures }}
+                    }
                 }
             }
         }
     }
-    IniRow<'a>(page: &'a Page, test: &'a IniTest) {
-        tr["data-field-start" = true] {
-            th["data-js-name" = &test.name] {
-                details."toggle-table" {
-                    summary { {test.name} }
-                }
-            }
+
+    Failures<'a>(failures: &'a [Failure]) {
+        @if !failures.is_empty() {
             td {
-                "cut: "
-                span[style = to_style(test.cut_time0, test.cut_time1)] {
-                    {relative_change(test.cut_time0, test.cut_time1)}
+                span[style = FAILURE_STYLE, title = failure_messages(failures)] {
+                    {describe_failures(failures)}
                 }
             }
-            td {
-                "draw: "
-                span[style = to_style(test.draw_time0, test.draw_time1)] {
-                    {relative_change(test.draw_time0, test.draw_time1)}
+        }
+    }
+
+    SuiteRow<'a>(page: &'a Page<'a>, suite: &'a Suite, test: &'a TestComparison) {
+        tr["data-field-start" = true] {
+            th["data-js-name" = &test.name] {
+                details."toggle-table" {
+                    summary {
+                        a[href = test_url(test.id, &page.machine2, &page.branch2)] {
+                            {test.name}
+                        }
+                    }
                 }
             }
-            td {
-                "mem: "
-                span[style = to_style(test.memory0, test.memory1)] {
-                    {relative_change(test.memory0, test.memory1)}
+            @for (metric, m) in suite.metrics.iter().zip(test.metrics.iter()) {
+                td {
+                    {metric.name} ": "
+                    span[style = to_style(m.status), title = m.summary()] {
+                        {relative_change(m.value0, m.value1)}
+                    }
                 }
             }
+            {Failures { failures: &test.failures[1] }}
         }
         tr[style = "display:none"] {
-            th[style = "text-align:right"] { "r" {page.revision_low} }
-            td { {format_time(test.cut_time0)} }
-            td { {format_time(test.draw_time0)} }
-            td { {format_mem(test.memory0)} }
+            th[style = "text-align:right"] { {&page.label_low} }
+            @for (metric, m) in suite.metrics.iter().zip(test.metrics.iter()) {
+                td { {format_value(metric, m.value0)} " (n=" {m.samples0.len()} ")" }
+            }
+            {Failures { failures: &test.failures[0] }}
         }
         tr[style = "display:none"] {
-            th[style = "text-align:right"] { "r" {page.revision_high} }
-            td { {format_time(test.cut_time1)} }
-            td { {format_time(test.draw_time1)} }
-            td { {format_mem(test.memory1)} }
+            th[style = "text-align:right"] { {&page.label_high} }
+            @for (metric, m) in suite.metrics.iter().zip(test.metrics.iter()) {
+                td { {format_value(metric, m.value1)} " (n=" {m.samples1.len()} ")" }
+            }
+            {Failures { failures: &test.failures[1] }}
         }
         tr[style = "display:none"] {
-            td[colspan = 4, class="chart", "data-chart-id" = &test.name] {}
+            td[
+                colspan = suite.metrics.len() + 1,
+                class = "chart",
+                "data-suite" = &suite.name,
+                "data-chart-id" = test.id
+            ] {}
         }
     }
 }
@@ -225,143 +598,523 @@ pub fn relative_change(v1: f64, v2: f64) -> String {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Regression,
+    Improvement,
+    Unchanged,
+}
+
+/// Classifies the change from `v1` to `v2`. Changes within the threshold or
+/// without statistical significance count as unchanged.
 #[allow(clippy::float_cmp)]
-pub fn to_style(v1: f64, v2: f64) -> &'static str {
+pub fn classify(
+    v1: f64,
+    v2: f64,
+    lower_is_better: bool,
+    threshold: f64,
+    significant: bool,
+) -> Status {
     let v = v2 / v1 - 1.0;
-    if v.is_nan() || v.is_infinite() || v == -1.0 || v > 0.05 {
-        "color:#e00;font-weight:bold"
-    } else if v < -0.05 {
-        "color:#0a0;font-weight:bold"
+    if v.is_nan() || v.is_infinite() || v == -1.0 {
+        return Status::Regression;
+    }
+    let v = if lower_is_better { v } else { -v };
+    if !significant {
+        Status::Unchanged
+    } else if v > threshold {
+        Status::Regression
+    } else if v < -threshold {
+        Status::Improvement
+    } else {
+        Status::Unchanged
+    }
+}
+
+pub fn to_style(status: Status) -> &'static str {
+    match status {
+        Status::Regression => "color:#e00;font-weight:bold",
+        Status::Improvement => "color:#0a0;font-weight:bold",
+        Status::Unchanged => "color:#aaa",
+    }
+}
+
+const FAILURE_STYLE: &str = "color:#fff;background:#e00;font-weight:bold;padding:0 0.3em";
+
+/// E.g. "2× crashed, 1× timeout".
+pub fn describe_failures(failures: &[Failure]) -> String {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for failure in failures {
+        *counts.entry(failure.status.name()).or_insert(0) += 1;
+    }
+    counts
+        .iter()
+        .map(|(status, count)| format!("{}× {}", count, status))
+        .join(", ")
+}
+
+/// Distinct error messages, one per line.
+pub fn failure_messages(failures: &[Failure]) -> String {
+    failures.iter().map(Failure::summary).unique().join("\n")
+}
+
+/// Commit subjects are shortened to this many characters in the revision
+/// selects.
+const SUBJECT_LENGTH: usize = 60;
+
+fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        text.to_string()
     } else {
-        "color:#aaa"
+        text.chars()
+            .take(length - 1)
+            .chain(std::iter::once('…'))
+            .collect()
     }
 }
 
-fn format_time(t: f64) -> String {
-    format!("{:.2}s", t)
+/// Like `Metric::format`, but a dash for sides without successful runs.
+pub fn format_value(metric: &Metric, v: f64) -> String {
+    if v.is_nan() {
+        "–".to_string()
+    } else {
+        metric.format(v)
+    }
 }
 
-fn format_mem(m: f64) -> String {
-    format!("{:.0} MB", m)
+pub fn format_percent(v: f64) -> String {
+    format!("{:+.1}%", 100.0 * v)
 }
 
-fn db_all_revisions(conn: &Connection, table: &str) -> rusqlite::Result<Vec<u32>> {
+/// Ratio that is > 1 when r2 is worse than r1.
+pub fn regression_ratio(metric: &Metric, m: &MetricComparison) -> f64 {
+    if metric.lower_is_better {
+        m.value1 / m.value0
+    } else {
+        m.value0 / m.value1
+    }
+}
+
+/// Revisions with runs on either `(machine, branch)` side, oldest first.
+fn db_all_revisions(
+    conn: &Connection,
+    registry: &Registry,
+    lowest_revision: i64,
+    sides: [(&str, &str); 2],
+) -> rusqlite::Result<Vec<String>> {
+    let runs = registry
+        .suites
+        .iter()
+        .map(|suite| {
+            format!(
+                concat!(
+                    "SELECT revision FROM {} ",
+                    "WHERE (machine = ?1 AND branch = ?2) OR (machine = ?3 AND branch = ?4)"
+                ),
+                suite.table
+            )
+        })
+        .join(" UNION ");
+    let query = format!(
+        "SELECT revision FROM revisions WHERE revision IN ({}) AND sequence >= {} ORDER BY sequence",
+        runs, lowest_revision
+    );
     Ok(conn
-        .prepare(&format!(
-            "SELECT DISTINCT revision FROM {} WHERE revision >= {} ORDER BY revision",
-            table, LOWEST_REVISION
-        ))?
-        .query_map(NO_PARAMS, |row| row.get(0))?
+        .prepare(&query)?
+        .query_map(
+            &[&sides[0].0, &sides[0].1, &sides[1].0, &sides[1].1],
+            |row| row.get(0),
+        )?
         .filter_map(|r| r.ok())
         .collect())
 }
 
-fn db_revision_comparison(
-    conn: &Connection,
-    revision1: u32,
-    revision2: u32,
-    order_by: &str,
-) -> rusqlite::Result<(Vec<CsbTest>, Vec<IniTest>)> {
-    let csb_order_by = match order_by {
-        "cut time" | "draw time" => "AVG(a.player_total_time) / AVG(b.player_total_time)",
-        "memory" => "AVG(a.memory_peak) / AVG(b.memory_peak)",
-        _ => "a.config_file",
-    };
-    let ini_order_by = match order_by {
-        "cut time" => "AVG(a.cutting_time) / AVG(b.cutting_time)",
-        "draw time" => "AVG(a.draw_time) / AVG(b.draw_time)",
-        "memory" => "AVG(a.memory_peak) / AVG(b.memory_peak)",
-        _ => "a.config_file",
-    };
-    Ok((
-        db_revision_comparison_csb(&conn, revision1, revision2, csb_order_by)?,
-        db_revision_comparison_ini(&conn, revision1, revision2, ini_order_by)?,
-    ))
+pub struct SuiteComparison<'a> {
+    pub suite: &'a Suite,
+    pub tests: Vec<TestComparison>,
+    /// Tests that only have runs on the second side.
+    pub added: Vec<OneSidedTest>,
+    /// Tests that only have runs on the first side.
+    pub removed: Vec<OneSidedTest>,
 }
 
-pub struct CsbTest {
-    name: String,
-    time0: f64,
-    time1: f64,
-    memory0: f64,
-    memory1: f64,
+pub struct OneSidedTest {
+    /// Id in the tests table.
+    pub id: i64,
+    pub name: String,
+    /// One aggregated value per metric of the suite.
+    pub values: Vec<f64>,
+    pub failures: Vec<Failure>,
 }
-fn db_revision_comparison_csb(
-    conn: &Connection,
-    revision1: u32,
-    revision2: u32,
-    order_by: &str,
-) -> rusqlite::Result<Vec<CsbTest>> {
-    let query = concat!(
-        "SELECT a.config_file, ",
-        "AVG(a.player_total_time), AVG(b.player_total_time), ",
-        "AVG(a.memory_peak), AVG(b.memory_peak) ",
-        "FROM processed_csb A ",
-        "INNER JOIN processed_csb b ON a.config_file = b.config_file ",
-        "WHERE a.revision=?1 AND b.revision=?2 GROUP BY a.config_file "
-    )
-    .to_string()
-        + &format!("ORDER BY {}", order_by);
 
-    Ok(conn
-        .prepare_cached(&query)?
-        .query_map(&[&revision1, &revision2], |row| {
-            let name: String = row.get(0)?;
-            let name = name.split("\\testcases\\").last().unwrap_or(&name);
-            Ok(CsbTest {
-                name: name.to_string(),
-                time0: row.get(1)?,
-                time1: row.get(2)?,
-                memory0: row.get(3)?,
-                memory1: row.get(4)?,
+pub struct TestComparison {
+    /// Id in the tests table.
+    pub id: i64,
+    pub name: String,
+    /// One entry per metric of the suite.
+    pub metrics: Vec<MetricComparison>,
+    /// Runs without measurements, per revision.
+    pub failures: [Vec<Failure>; 2],
+}
+
+pub struct MetricComparison {
+    pub samples0: Vec<f64>,
+    pub samples1: Vec<f64>,
+    pub value0: f64,
+    pub value1: f64,
+    /// Relative change beyond which the difference counts.
+    pub threshold: f64,
+    pub welch: Option<stats::Welch>,
+    pub confidence: f64,
+    /// Whether the difference passed the significance test, or could not be tested.
+    pub significant: bool,
+    pub status: Status,
+}
+
+impl MetricComparison {
+    /// Without at least two repetitions per revision no significance test is
+    /// possible, and only the threshold decides.
+    fn new(
+        metric: &Metric,
+        settings: &Settings,
+        aggregation: Aggregation,
+        threshold: f64,
+        samples0: Vec<f64>,
+        samples1: Vec<f64>,
+    ) -> Self {
+        let value0 = aggregation.apply(&samples0);
+        let value1 = aggregation.apply(&samples1);
+        let confidence = 1.0 - settings.significance_level;
+        let welch = stats::welch(&samples0, &samples1, confidence);
+        let significant = welch
+            .as_ref()
+            .is_none_or(|w| w.p_value < settings.significance_level);
+        let status = classify(
+            value0,
+            value1,
+            metric.lower_is_better,
+            threshold,
+            significant,
+        );
+        Self {
+            samples0,
+            samples1,
+            value0,
+            value1,
+            threshold,
+            welch,
+            confidence,
+            significant,
+            status,
+        }
+    }
+
+    fn summary(&self) -> String {
+        let test = match &self.welch {
+            Some(w) => format!(
+                "p = {:.3}, {:.0}% CI [{}, {}]",
+                w.p_value,
+                100.0 * self.confidence,
+                format_percent(w.relative_change_ci.0),
+                format_percent(w.relative_change_ci.1)
+            ),
+            None => "too few repetitions for a significance test".to_string(),
+        };
+        format!("{}, threshold ±{:.1}%", test, 100.0 * self.threshold)
+    }
+}
+
+impl<'a> SuiteComparison<'a> {
+    pub fn to_json(&self) -> serde_json::Value {
+        let tests: Vec<_> = self
+            .tests
+            .iter()
+            .map(|test| {
+                let metrics: Vec<_> = self
+                    .suite
+                    .metrics
+                    .iter()
+                    .zip(test.metrics.iter())
+                    .map(|(metric, m)| {
+                        json!({
+                            "metric": metric.name,
+                            "title": metric.title,
+                            "unit": metric.unit,
+                            "lower_is_better": metric.lower_is_better,
+                            "value0": m.value0,
+                            "value1": m.value1,
+                            "samples0": m.samples0,
+                            "samples1": m.samples1,
+                            "relative_change": m.value1 / m.value0 - 1.0,
+                            "threshold": m.threshold,
+                            "p_value": m.welch.as_ref().map(|w| w.p_value),
+                            "relative_change_ci": m.welch.as_ref().map(|w| w.relative_change_ci),
+                            "status": m.status,
+                        })
+                    })
+                    .collect();
+                json!({
+                    "id": test.id,
+                    "name": test.name,
+                    "metrics": metrics,
+                    "failures0": test.failures[0],
+                    "failures1": test.failures[1],
+                })
             })
-        })?
-        .filter_map(|r| r.ok())
-        .collect())
+            .collect();
+        let one_sided = |tests: &[OneSidedTest]| -> Vec<_> {
+            tests
+                .iter()
+                .map(|test| {
+                    let values: serde_json::Map<_, _> = self
+                        .suite
+                        .metrics
+                        .iter()
+                        .zip(test.values.iter())
+                        .map(|(metric, v)| (metric.name.clone(), json!(v)))
+                        .collect();
+                    json!({
+                        "id": test.id,
+                        "name": test.name,
+                        "values": values,
+                        "failures": test.failures,
+                    })
+                })
+                .collect()
+        };
+        json!({
+            "suite": self.suite.name,
+            "title": self.suite.title,
+            "tests": tests,
+            "added": one_sided(&self.added),
+            "removed": one_sided(&self.removed),
+        })
+    }
 }
 
-pub struct IniTest {
-    name: String,
-    cut_time0: f64,
-    cut_time1: f64,
-    draw_time0: f64,
-    draw_time1: f64,
-    memory0: f64,
-    memory1: f64,
+/// Unknown revisions and revisions without runs on `machine` and `branch`
+/// are errors: there is nothing to compare.
+pub fn validate_revisions(
+    conn: &Connection,
+    registry: &Registry,
+    machine: &str,
+    branch: &str,
+    revisions: &[&str],
+) -> Result<(), String> {
+    for revision in revisions.iter() {
+        if !db_revision_known(conn, revision).map_err(|e| e.to_string())? {
+            return Err(format!("unknown revision {}", revision));
+        }
+        if !db_has_runs(conn, registry, machine, branch, revision).map_err(|e| e.to_string())? {
+            return Err(format!(
+                "no runs of {} on machine '{}' ({})",
+                revision_label(revision),
+                machine,
+                branch_label(branch)
+            ));
+        }
+    }
+    Ok(())
 }
-fn db_revision_comparison_ini(
+
+#[allow(clippy::too_many_arguments)]
+pub fn db_revision_comparison<'a>(
     conn: &Connection,
-    revision1: u32,
-    revision2: u32,
+    registry: &'a Registry,
+    settings: &Settings,
+    aggregation: Aggregation,
+    machine: &str,
+    branch: &str,
+    revision1: &str,
+    revision2: &str,
     order_by: &str,
-) -> rusqlite::Result<Vec<IniTest>> {
-    let query = concat!(
-        "SELECT a.config_file, ",
-        "AVG(a.cutting_time), AVG(b.cutting_time), ",
-        "AVG(a.draw_time), AVG(b.draw_time), ",
-        "AVG(a.memory_peak), AVG(b.memory_peak) ",
-        "FROM processed_ini a ",
-        "INNER JOIN processed_ini b ON a.config_file = b.config_file ",
-        "WHERE a.revision=?1 AND b.revision=?2 GROUP BY a.config_file "
+) -> rusqlite::Result<Vec<SuiteComparison<'a>>> {
+    db_comparison(
+        conn,
+        registry,
+        settings,
+        aggregation,
+        [(machine, branch, revision1), (machine, branch, revision2)],
+        order_by,
     )
-    .to_string()
-        + &format!("ORDER BY {}", order_by);
+}
 
-    Ok(conn
-        .prepare_cached(&query)?
-        .query_map(&[&revision1, &revision2], |row| {
-            let name: String = row.get(0)?;
-            let name = name.split("\\testcases\\").last().unwrap_or(&name);
-            Ok(IniTest {
-                name: name.to_string(),
-                cut_time0: row.get(1)?,
-                cut_time1: row.get(2)?,
-                draw_time0: row.get(3)?,
-                draw_time1: row.get(4)?,
-                memory0: row.get(5)?,
-                memory1: row.get(6)?,
-            })
-        })?
-        .filter_map(|r| r.ok())
-        .collect())
+/// Compares the runs of two `(machine, branch, revision)` sides.
+pub fn db_comparison<'a>(
+    conn: &Connection,
+    registry: &'a Registry,
+    settings: &Settings,
+    aggregation: Aggregation,
+    sides: [(&str, &str, &str); 2],
+    order_by: &str,
+) -> rusqlite::Result<Vec<SuiteComparison<'a>>> {
+    registry
+        .suites
+        .iter()
+        .map(|suite| {
+            let mut comparison = db_suite_comparison(conn, suite, settings, aggregation, sides)?;
+            sort_tests(&mut comparison.tests, suite, order_by);
+            Ok(comparison)
+        })
+        .collect()
+}
+
+/// Sorts by name, by the regression ratio of the metric with the given title,
+/// or by the largest regression ratio of any metric.
+fn sort_tests(tests: &mut [TestComparison], suite: &Suite, order_by: &str) {
+    if order_by == "name" {
+        tests.sort_by(|a, b| a.name.cmp(&b.name));
+        return;
+    }
+    let metric_index = suite.metrics.iter().position(|m| m.title == order_by);
+    let key = |test: &TestComparison| match metric_index {
+        Some(i) => regression_ratio(&suite.metrics[i], &test.metrics[i]),
+        None => suite
+            .metrics
+            .iter()
+            .zip(test.metrics.iter())
+            .map(|(metric, m)| regression_ratio(metric, m))
+            .fold(f64::NEG_INFINITY, f64::max),
+    };
+    tests.sort_by(|a, b| key(b).partial_cmp(&key(a)).unwrap_or(Ordering::Equal));
+}
+
+/// Per test and revision: whether it ran at all, the samples of successful
+/// runs per metric, and the failed runs.
+struct TestRuns {
+    present: [bool; 2],
+    samples: [Vec<Vec<f64>>; 2],
+    failures: [Vec<Failure>; 2],
+}
+
+/// Fetches the individual repetitions of both sides. Tests that only have
+/// runs on one side are reported as added or removed. Failed runs are kept
+/// apart and don't contribute samples.
+fn db_suite_comparison<'a>(
+    conn: &Connection,
+    suite: &'a Suite,
+    settings: &Settings,
+    aggregation: Aggregation,
+    sides: [(&str, &str, &str); 2],
+) -> rusqlite::Result<SuiteComparison<'a>> {
+    let query = format!(
+        concat!(
+            "SELECT t.config_file, r.sequence, t.machine, t.branch, t.revision, t.status, ",
+            "t.error_message, {} ",
+            "FROM {} t JOIN revisions r ON r.revision = t.revision ",
+            "WHERE (t.machine = ?1 AND t.branch = ?2 AND t.revision = ?3) ",
+            "OR (t.machine = ?4 AND t.branch = ?5 AND t.revision = ?6)"
+        ),
+        suite
+            .metrics
+            .iter()
+            .map(|m| format!("t.{}", m.column))
+            .join(", "),
+        suite.table
+    );
+    let mut stmt = conn.prepare_cached(&query)?;
+    let rows = stmt
+        .query_map(
+            params![sides[0].0, sides[0].1, sides[0].2, sides[1].0, sides[1].1, sides[1].2],
+            |row| {
+                let mut values: Vec<Option<f64>> = Vec::new();
+                for i in 0..suite.metrics.len() {
+                    values.push(row.get(i + 7)?);
+                }
+                let failure = Failure {
+                    status: RunStatus::from_db(&row.get::<_, String>(5)?),
+                    error_message: row.get(6)?,
+                };
+                Ok((
+                    (row.get::<_, String>(0)?, row.get::<_, i64>(1)?),
+                    (
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                    ),
+                    failure,
+                    values,
+                ))
+            },
+        )?
+        .filter_map(|r| r.ok());
+
+    // Runs of renamed tests count for the test's current path.
+    let aliases = db_aliases(conn, &suite.name)?;
+    let tests = db_suite_tests(conn, &suite.name)?;
+    let thresholds = db_thresholds(
+        conn,
+        suite,
+        [(sides[0].0, sides[0].1), (sides[1].0, sides[1].1)],
+    )?;
+    let empty = vec![Vec::new(); suite.metrics.len()];
+    // By name, then id: names of different tests may collide.
+    let mut runs: BTreeMap<(String, i64), TestRuns> = BTreeMap::new();
+    for ((path, sequence), side_key, failure, values) in rows {
+        let (id, name) = match tests.get(aliases.resolve(&path, sequence)) {
+            Some(test) => test,
+            None => continue,
+        };
+        let entry = runs.entry((name.clone(), *id)).or_insert_with(|| TestRuns {
+            present: [false, false],
+            samples: [empty.clone(), empty.clone()],
+            failures: [Vec::new(), Vec::new()],
+        });
+        for (side, (machine, branch, revision)) in sides.iter().enumerate() {
+            if side_key.0 != *machine || side_key.1 != *branch || side_key.2 != *revision {
+                continue;
+            }
+            entry.present[side] = true;
+            if failure.status != RunStatus::Ok {
+                entry.failures[side].push(failure.clone());
+                continue;
+            }
+            for (i, value) in values.iter().enumerate() {
+                entry.samples[side][i].extend(value);
+            }
+        }
+    }
+
+    let mut comparison = SuiteComparison {
+        suite,
+        tests: Vec::new(),
+        added: Vec::new(),
+        removed: Vec::new(),
+    };
+    for ((name, id), test_runs) in runs {
+        let TestRuns {
+            present,
+            samples: [s0, s1],
+            failures: [f0, f1],
+        } = test_runs;
+        let one_sided = |samples: Vec<Vec<f64>>, failures: Vec<Failure>| OneSidedTest {
+            id,
+            name: name.clone(),
+            values: samples.iter().map(|s| aggregation.apply(s)).collect(),
+            failures,
+        };
+        match present {
+            [true, true] => {
+                let metrics = suite
+                    .metrics
+                    .iter()
+                    .zip(s0.into_iter().zip(s1))
+                    .map(|(metric, (s0, s1))| {
+                        let threshold = thresholds.threshold(settings, id, &metric.name);
+                        MetricComparison::new(metric, settings, aggregation, threshold, s0, s1)
+                    })
+                    .collect();
+                comparison.tests.push(TestComparison {
+                    id,
+                    name: name.clone(),
+                    metrics,
+                    failures: [f0, f1],
+                });
+            }
+            [true, false] => comparison.removed.push(one_sided(s0, f0)),
+            _ => comparison.added.push(one_sided(s1, f1)),
+        }
+    }
+    Ok(comparison)
 }
@@ -0,0 +1,153 @@
+use crate::{check, commits, comparison, metrics, testcases};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Read from the working directory if neither `--config` nor
+/// `BENCHTABLE_CONFIG` name a file. Unlike a named file, it may be missing.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Default, Deserialize)]
+pub struct Config {
+    /// Relative to the directory of the config file.
+    #[serde(default)]
+    pub sqlite_db: PathBuf,
+    #[serde(default)]
+    pub server: ServerSettings,
+    #[serde(default)]
+    pub suites: Vec<metrics::Suite>,
+    #[serde(default)]
+    pub tests: testcases::TestSettings,
+    #[serde(default)]
+    pub comparison: comparison::Settings,
+    #[serde(default)]
+    pub check: check::CheckConfig,
+    #[serde(default)]
+    pub commits: commits::CommitSettings,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub bind: String,
+    /// Serves the static assets from this directory instead of the copies
+    /// embedded into the binary, for development. Relative to the directory
+    /// of the config file.
+    pub static_dir: Option<PathBuf>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8000".to_string(),
+            static_dir: None,
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn address(&self) -> Result<SocketAddr, String> {
+        self.bind
+            .parse()
+            .map_err(|_| format!("invalid bind address '{}'", self.bind))
+    }
+}
+
+/// Settings that can also be given as `(command-line flag, environment
+/// variable)`. Flags take precedence over environment variables, which take
+/// precedence over the config file.
+pub static OVERRIDES: &[(&str, &str)] = &[
+    ("--db", "BENCHTABLE_SQLITE_DB"),
+    ("--bind", "BENCHTABLE_BIND"),
+    ("--static-dir", "BENCHTABLE_STATIC_DIR"),
+    ("--lowest-revision", "BENCHTABLE_LOWEST_REVISION"),
+];
+
+impl Config {
+    fn set(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        match flag {
+            "--db" => self.sqlite_db = PathBuf::from(value),
+            "--bind" => self.server.bind = value.to_string(),
+            "--static-dir" => self.server.static_dir = Some(PathBuf::from(value)),
+            "--lowest-revision" => {
+                self.comparison.lowest_revision = value
+                    .parse()
+                    .map_err(|_| format!("invalid sequence number '{}'", value))?
+            }
+            _ => unreachable!("no setting for {}", flag),
+        }
+        Ok(())
+    }
+}
+
+/// Reads the config file named by `--config` or `BENCHTABLE_CONFIG`, or
+/// `config.toml` if it exists, and applies the overrides. Global flags are
+/// removed from `args`, leaving the command and its arguments.
+pub fn load(args: &mut Vec<String>) -> Result<Config, String> {
+    let named = take_flag(args, "--config")?.or_else(|| env_var("BENCHTABLE_CONFIG"));
+    let path = PathBuf::from(named.as_ref().map_or(DEFAULT_CONFIG_FILE, String::as_str));
+    let mut config: Config = match std::fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound && named.is_none() => {
+            Config::default()
+        }
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    config.sqlite_db = resolve(base, &config.sqlite_db);
+    config.server.static_dir = config
+        .server
+        .static_dir
+        .as_ref()
+        .map(|dir| resolve(base, dir));
+
+    for (flag, variable) in OVERRIDES.iter() {
+        let (source, value) = match take_flag(args, flag)? {
+            Some(value) => (flag, value),
+            None => match env_var(variable) {
+                Some(value) => (variable, value),
+                None => continue,
+            },
+        };
+        config
+            .set(flag, &value)
+            .map_err(|e| format!("{}: {}", source, e))?;
+    }
+
+    if config.sqlite_db.as_os_str().is_empty() {
+        return Err(format!(
+            "no database configured, set sqlite_db in {}, BENCHTABLE_SQLITE_DB or --db",
+            path.display()
+        ));
+    }
+    config.server.address()?;
+    metrics::validate_suites(&config.suites).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(config)
+}
+
+fn resolve(base: &Path, path: &Path) -> PathBuf {
+    if path.as_os_str().is_empty() || path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base.join(path)
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// Removes `name` and its value from `args`.
+fn take_flag(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let i = match args.iter().position(|a| a == name) {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    match args.get(i + 1) {
+        Some(value) if !value.starts_with("--") => {
+            let value = value.clone();
+            args.drain(i..i + 2);
+            Ok(Some(value))
+        }
+        _ => Err(format!("{} expects a value", name)),
+    }
+}
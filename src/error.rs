@@ -0,0 +1,163 @@
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::fmt;
+
+pub const HTML: &str = "text/html; charset=utf-8";
+pub const JSON: &str = "text/json";
+pub const MARKDOWN: &str = "text/markdown; charset=utf-8";
+pub const TEXT: &str = "text/plain; charset=utf-8";
+
+/// Errors of the web routes. Each kind maps to one status code.
+#[derive(Debug)]
+pub enum Error {
+    /// No database connection could be checked out of the pool.
+    Pool(r2d2::Error),
+    Sql(rusqlite::Error),
+    /// A response could not be built.
+    Http(http::Error),
+    NotFound(String),
+    BadRequest(String),
+    Forbidden(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<r2d2::Error> for Error {
+    fn from(e: r2d2::Error) -> Self {
+        Error::Pool(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sql(e)
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(e: http::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Pool(e) => write!(f, "database unavailable: {}", e),
+            Error::Sql(e) => write!(f, "SQL error: {}", e),
+            Error::Http(e) => write!(f, "{}", e),
+            Error::NotFound(message) | Error::BadRequest(message) | Error::Forbidden(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl Error {
+    pub fn status(&self) -> http::StatusCode {
+        match self {
+            Error::Pool(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+            Error::Sql(_) | Error::Http(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Error::NotFound(_) => http::StatusCode::NOT_FOUND,
+            Error::BadRequest(_) => http::StatusCode::BAD_REQUEST,
+            Error::Forbidden(_) => http::StatusCode::FORBIDDEN,
+        }
+    }
+
+    /// Error body in the format of the route's regular responses.
+    fn body(&self, content_type: &str) -> String {
+        let status = self.status();
+        let title = status.canonical_reason().unwrap_or("Error");
+        if content_type == JSON {
+            json!({
+                "status": status.as_u16(),
+                "error": title,
+                "detail": self.to_string(),
+            })
+            .to_string()
+        } else if content_type == HTML {
+            ErrorPage {
+                status: status.as_u16(),
+                reason: title,
+                detail: &self.to_string(),
+            }
+            .to_string()
+        } else {
+            format!("{} {}: {}\n", status.as_u16(), title, self)
+        }
+    }
+}
+
+/// Turns the result of a route into a response with `content_type`. Errors
+/// get a body of the same type, so that scripts can rely on JSON and people
+/// see a page.
+pub fn respond(
+    result: Result<String>,
+    content_type: &'static str,
+) -> std::result::Result<http::Response<String>, tower_web::Error> {
+    let response = result.and_then(|body| {
+        Ok(http::Response::builder()
+            .header(http::header::CONTENT_TYPE, content_type)
+            .body(body)?)
+    });
+    respond_with(response, content_type)
+}
+
+/// Like `respond`, for routes that build their own responses.
+pub fn respond_with<B: From<String>>(
+    result: Result<http::Response<B>>,
+    content_type: &'static str,
+) -> std::result::Result<http::Response<B>, tower_web::Error> {
+    let e = match result {
+        Ok(response) => return Ok(response),
+        Err(e) => e,
+    };
+    if let Error::Pool(_) | Error::Sql(_) | Error::Http(_) = e {
+        eprintln!("error: {}", e);
+    }
+    http::Response::builder()
+        .status(e.status())
+        .header(http::header::CONTENT_TYPE, content_type)
+        .body(B::from(e.body(content_type)))
+        .map_err(|e| {
+            tower_web::Error::new(
+                "Internal Server Error",
+                &e.to_string(),
+                http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        })
+}
+
+/// Parses the raw query string of a route. Routes take it unparsed, so that
+/// malformed parameters get the same error bodies as everything else.
+pub fn parse_query<T: DeserializeOwned>(query_string: Option<String>) -> Result<T> {
+    serde_urlencoded::from_str(query_string.as_ref().map_or("", String::as_str))
+        .map_err(|e| Error::BadRequest(format!("invalid query: {}", e)))
+}
+
+pub fn parse_json<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body).map_err(|e| Error::BadRequest(format!("invalid JSON: {}", e)))
+}
+
+pub fn parse_id(id: &str) -> Result<i64> {
+    id.parse()
+        .map_err(|_| Error::BadRequest(format!("invalid id '{}'", id)))
+}
+
+markup::define! {
+    ErrorPage<'a>(status: u16, reason: &'a str, detail: &'a str) {
+        {markup::doctype()}
+        html {
+            head {
+                title { {status} " " {reason} }
+                link[rel="stylesheet", href="/static/style.css"] {}
+                link[rel="icon", type="image/png", href="/static/icon.png"] {}
+            }
+            body {
+                h1 { {status} " " {reason} }
+                p { {detail} }
+                p { a[href="/"] { "Back to the latest comparison" } }
+            }
+        }
+    }
+}
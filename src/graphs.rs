@@ -1,11 +1,21 @@
-use crate::LOWEST_REVISION;
+use crate::aliases::db_aliases;
+use crate::branches::{branch_label, MAIN_LINE};
+use crate::changepoints::db_changepoints;
+use crate::commits::db_commits;
+use crate::comparison::{describe_failures, failure_messages, Settings};
+use crate::error;
+use crate::ingest::{Failure, RunStatus};
+use crate::machines::db_test_series;
+use crate::metrics::{Registry, Suite};
+use crate::revisions::revision_label;
+use crate::stats::Aggregation;
+use crate::testcases::{self, db_suite_tests};
 use itertools::Itertools;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::Connection;
+use rusqlite::{params, Connection, ToSql};
 use serde_json::json;
-use std::collections::HashMap;
-use std::iter::FromIterator;
+use std::collections::{BTreeSet, HashMap};
 
 /*
 Color scheme (copied from Chart.js examples):
@@ -19,196 +29,389 @@ purple: "rgb(153, 102, 255)",
 grey: "rgb(201, 203, 207)"
 */
 
-#[derive(Extract)]
+const FAILURE_COLOR: &str = "rgb(255, 0, 0)";
+
+/// Line styles that tell the series of different machines and branches apart.
+const MACHINE_DASHES: &[&[u32]] = &[&[], &[8, 4], &[2, 2], &[8, 4, 2, 4]];
+
+#[derive(Deserialize)]
 pub struct FileGraphQuery {
-    id: String,
+    /// Id in the tests table.
+    id: i64,
+    agg: Option<String>,
+    /// Without a machine, every machine that ran the test gets its own series.
+    machine: Option<String>,
+    /// Overlaid on the main line.
+    branch: Option<String>,
 }
 pub fn api_file_graph_json(
     db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
     file_type: String,
     query: FileGraphQuery,
-) -> Result<String, tower_web::Error> {
-    let (sql_table, sql_columns, column_titles): (&str, Vec<&str>, Vec<&str>) =
-        match file_type.as_str() {
-            "csb" => (
-                "processed_csb",
-                vec!["memory_peak", "player_total_time"],
-                vec!["Memory", "Run Time"],
-            ),
-            "ini" => (
-                "processed_ini",
-                vec!["memory_peak", "cutting_time", "draw_time"],
-                vec!["Memory", "Cut Time", "Draw Time"],
-            ),
-            _ => {
-                return Err(tower_web::Error::new(
-                    "Bad Request",
-                    "unexpected file type",
-                    http::StatusCode::BAD_REQUEST,
-                ));
-            }
-        };
+) -> error::Result<String> {
+    let aggregation = parse_aggregation(query.agg.as_deref(), settings)?;
+    let conn = db.get()?;
+    let (test, suite) = testcases::find(&conn, registry, query.id)?;
+    if suite.name != file_type {
+        return Err(error::Error::NotFound(format!(
+            "no test {} in suite '{}'",
+            query.id, file_type
+        )));
+    }
 
-    let conn = db.get().unwrap();
-    let revision_info = db_revision_history_for_file(&conn, sql_table, &sql_columns, &query.id)
-        .map_err(|e| {
-            tower_web::Error::new(
-                "SQL Error",
-                &e.to_string(),
-                http::StatusCode::INTERNAL_SERVER_ERROR,
-            )
-        })?;
-    let reference_stats = &revision_info[0].stats;
-    let labels: Vec<_> = revision_info.iter().map(|r| r.revision).collect();
-    let colors = vec![
-        "rgb(54, 162, 235)",
-        "rgb(255, 159, 64)",
-        "rgb(75, 192, 192)",
+    let branches = [
+        MAIN_LINE,
+        query.branch.as_ref().map_or(MAIN_LINE, String::as_str),
     ];
-    let datasets: Vec<_> = column_titles
+    let histories = db_test_series(&conn, suite, &test.path).and_then(|series| {
+        series
+            .into_iter()
+            .filter(|(machine, branch)| {
+                query.machine.as_ref().is_none_or(|m| m == machine)
+                    && branches.contains(&branch.as_str())
+            })
+            .map(|(machine, branch)| {
+                let history = db_revision_history_for_file(
+                    &conn,
+                    suite,
+                    &test.path,
+                    &machine,
+                    &branch,
+                    settings.lowest_revision,
+                    aggregation,
+                )?;
+                Ok(((machine, branch), history))
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()
+    })?;
+    if histories.is_empty() {
+        return Err(error::Error::NotFound(format!(
+            "no runs of test '{}' on {}",
+            test.name,
+            query.machine.as_ref().map_or("any machine", String::as_str)
+        )));
+    }
+    let changepoints = db_changepoints(
+        &conn,
+        Some(suite.name.as_str()),
+        Some(test.path.as_str()),
+        query.machine.as_deref(),
+        None,
+        1.0 - settings.significance_level,
+    )?;
+
+    let revisions: Vec<&str> = histories
+        .iter()
+        .flat_map(|(_, history)| history.iter().map(|r| (r.sequence, r.revision.as_str())))
+        .collect::<BTreeSet<_>>()
         .into_iter()
-        .enumerate()
-        .map(|(i, title)| {
-            let data: Vec<_> = revision_info
+        .map(|(_, revision)| revision)
+        .collect();
+    let labels: Vec<String> = revisions.iter().map(|r| revision_label(r)).collect();
+    let commits = db_commits(&conn, &revisions)?;
+    // Aligned with the labels, `null` where no commit log was imported.
+    let subjects: Vec<_> = revisions
+        .iter()
+        .map(|r| commits.get(*r).map(|c| c.subject()))
+        .collect();
+    let several_machines = histories.iter().map(|((m, _), _)| m).unique().count() > 1;
+    let several_branches = histories.iter().map(|((_, b), _)| b).unique().count() > 1;
+    let mut datasets = Vec::new();
+    for (n, ((machine, branch), history)) in histories.iter().enumerate() {
+        let by_revision: HashMap<&str, &RevisionInfos> =
+            history.iter().map(|r| (r.revision.as_str(), r)).collect();
+        // One entry per label, so that series of machines and branches with
+        // different revisions line up.
+        let points = |point: &dyn Fn(&RevisionInfos) -> serde_json::Value| -> Vec<_> {
+            revisions
                 .iter()
-                .map(|r| {
+                .map(|revision| by_revision.get(revision).map_or(json!(null), |r| point(r)))
+                .collect()
+        };
+        let label = |title: &str| {
+            let mut series = Vec::new();
+            if several_machines {
+                series.push(machine.as_str());
+            }
+            if several_branches {
+                series.push(branch_label(branch));
+            }
+            if series.is_empty() {
+                title.to_string()
+            } else {
+                format!("{} ({})", title, series.join(", "))
+            }
+        };
+        let border_dash = MACHINE_DASHES[n % MACHINE_DASHES.len()];
+
+        // Normalize each metric by its first measured value; revisions where
+        // every run failed have no values.
+        let reference_stats: Vec<f64> = (0..suite.metrics.len())
+            .map(|i| {
+                history
+                    .iter()
+                    .map(|r| r.stats[i])
+                    .find(|v| v.is_finite())
+                    .unwrap_or(f64::NAN)
+            })
+            .collect();
+        for (i, metric) in suite.metrics.iter().enumerate() {
+            let data = points(&|r| {
+                json!({
+                "x": revision_label(&r.revision),
+                "revision": r.revision,
+                "y": r.stats[i] / reference_stats[i],
+                "v": r.stats[i]})
+            });
+            datasets.push(json!({
+                "label": label(&metric.title),
+                "unit": metric.unit,
+                "backgroundColor": metric.color,
+                "borderColor": metric.color,
+                "borderDash": border_dash,
+                "spanGaps": true,
+                "fill": false,
+                "data": data
+            }));
+        }
+
+        // One marker dataset per metric, with a point only at detected change points.
+        for (i, metric) in suite.metrics.iter().enumerate() {
+            let is_changepoint = |revision: &str| {
+                changepoints.iter().any(|c| {
+                    c.machine == *machine
+                        && c.branch == *branch
+                        && c.metric == metric.name
+                        && c.revision == revision
+                })
+            };
+            if !history.iter().any(|r| is_changepoint(&r.revision)) {
+                continue;
+            }
+            let data = points(&|r| {
+                if is_changepoint(&r.revision) {
                     json!({
-                    "x": r.revision,
+                    "x": revision_label(&r.revision),
+                    "revision": r.revision,
                     "y": r.stats[i] / reference_stats[i],
                     "v": r.stats[i]})
-                })
-                .collect();
-            json!({
-                "label": title,
-                "backgroundColor": colors[i],
-                "borderColor": colors[i],
+                } else {
+                    json!(null)
+                }
+            });
+            datasets.push(json!({
+                "label": label(&format!("{} change", metric.title)),
+                "unit": metric.unit,
+                "backgroundColor": metric.color,
+                "borderColor": metric.color,
+                "showLine": false,
+                "pointStyle": "triangle",
+                "pointRadius": 8,
                 "fill": false,
                 "data": data
-            })
-        })
-        .collect();
+            }));
+        }
 
-    Ok(json!({"labels": labels, "datasets": datasets}).to_string())
+        if history.iter().any(|r| !r.failures.is_empty()) {
+            let data = points(&|r| {
+                if r.failures.is_empty() {
+                    json!(null)
+                } else {
+                    json!({
+                    "x": revision_label(&r.revision),
+                    "revision": r.revision,
+                    "y": 0,
+                    "failure": format!("{} ({})",
+                        describe_failures(&r.failures),
+                        failure_messages(&r.failures))})
+                }
+            });
+            datasets.push(json!({
+                "label": label("Failed runs"),
+                "backgroundColor": FAILURE_COLOR,
+                "borderColor": FAILURE_COLOR,
+                "showLine": false,
+                "pointStyle": "crossRot",
+                "pointRadius": 8,
+                "borderWidth": 3,
+                "fill": false,
+                "data": data
+            }));
+        }
+    }
+
+    Ok(json!({
+        "labels": labels,
+        "subjects": subjects,
+        "datasets": datasets,
+        "changepoints": changepoints
+    })
+    .to_string())
+}
+
+pub fn parse_aggregation(agg: Option<&str>, settings: &Settings) -> error::Result<Aggregation> {
+    Aggregation::from_arg(agg, settings.aggregation).map_err(error::Error::BadRequest)
 }
 
-struct RevisionInfos {
-    revision: u32,
-    stats: Vec<f64>,
+pub struct RevisionInfos {
+    pub revision: String,
+    /// Position in the revisions table.
+    pub sequence: i64,
+    /// Aggregated over the successful runs; NaN if there were none.
+    pub stats: Vec<f64>,
+    /// Values of the successful runs per metric.
+    pub samples: Vec<Vec<f64>>,
+    pub failures: Vec<Failure>,
 }
-fn db_revision_history_for_file(
+
+/// History of the test at `config_file`, including the runs it had under
+/// other paths before being renamed.
+pub fn db_revision_history_for_file(
     conn: &Connection,
-    table: &str,
-    columns: &[&str],
+    suite: &Suite,
     config_file: &str,
+    machine: &str,
+    branch: &str,
+    lowest_revision: i64,
+    aggregation: Aggregation,
 ) -> rusqlite::Result<Vec<RevisionInfos>> {
-    let column_str = columns
-        .iter()
-        .format_with(",", |v, f| f(&format_args!("AVG({})", v)));
+    let aliases = db_aliases(conn, &suite.name)?;
+    let sources = aliases.sources(config_file);
     let mut stmt = conn.prepare_cached(&format!(
         concat!(
-            "SELECT revision, {} FROM {} ",
-            "WHERE config_file LIKE ?1 ",
-            "AND revision >= {} ",
-            "GROUP BY revision ",
-            "ORDER BY revision"
+            "SELECT t.revision, r.sequence, t.config_file, t.status, t.error_message, {} ",
+            "FROM {} t JOIN revisions r ON r.revision = t.revision ",
+            "WHERE t.machine = ?1 AND t.branch = ?2 ",
+            "AND t.config_file IN ({}) ",
+            "AND r.sequence >= {} ",
+            "ORDER BY r.sequence, t.revision"
         ),
-        column_str, table, LOWEST_REVISION
+        suite
+            .metrics
+            .iter()
+            .map(|m| format!("t.{}", m.column))
+            .join(","),
+        suite.table,
+        (0..sources.len()).map(|i| format!("?{}", i + 3)).join(", "),
+        lowest_revision
     ))?;
-    let results = stmt.query_map(&[&config_file], |r| {
-        let mut stats = Vec::new();
-        for i in 0..columns.len() {
-            stats.push(r.get(i + 1)?);
+    let mut params: Vec<&dyn ToSql> = vec![&machine, &branch];
+    params.extend(sources.iter().map(|s| s as &dyn ToSql));
+    let rows = stmt
+        .query_map(&params, |r| {
+            let mut values: Vec<Option<f64>> = Vec::new();
+            for i in 0..suite.metrics.len() {
+                values.push(r.get(i + 5)?);
+            }
+            let failure = Failure {
+                status: RunStatus::from_db(&r.get::<_, String>(3)?),
+                error_message: r.get(4)?,
+            };
+            Ok((
+                (r.get::<_, String>(0)?, r.get::<_, i64>(1)?),
+                r.get::<_, String>(2)?,
+                failure,
+                values,
+            ))
+        })?
+        .filter_map(|r| r.ok())
+        .filter(|((_, sequence), path, _, _)| aliases.resolve(path, *sequence) == config_file)
+        .map(|(revision, _, failure, values)| (revision, failure, values));
+
+    let mut results = Vec::new();
+    for ((revision, sequence), runs) in &rows.chunk_by(|(revision, _, _)| revision.clone()) {
+        let mut samples = vec![Vec::new(); suite.metrics.len()];
+        let mut failures = Vec::new();
+        for (_, failure, values) in runs {
+            if failure.status != RunStatus::Ok {
+                failures.push(failure);
+                continue;
+            }
+            for (i, value) in values.into_iter().enumerate() {
+                samples[i].extend(value);
+            }
         }
-        Ok(RevisionInfos {
-            revision: r.get(0)?,
-            stats,
-        })
-    })?;
-    Ok(results.filter_map(|r| r.ok()).collect())
+        results.push(RevisionInfos {
+            revision,
+            sequence,
+            stats: samples.iter().map(|s| aggregation.apply(s)).collect(),
+            samples,
+            failures,
+        });
+    }
+    Ok(results)
 }
 
-#[derive(Extract)]
+#[derive(Deserialize)]
 pub struct AllGraphQuery {
-    r1: u32,
-    r2: u32,
+    r1: String,
+    r2: String,
+    agg: Option<String>,
+    /// Without a machine, each test gets one series per machine.
+    machine: Option<String>,
+    /// Defaults to the main line.
+    branch: Option<String>,
 }
 pub fn api_all_graph_json(
     db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
     file_type: String,
     query: AllGraphQuery,
-) -> Result<String, tower_web::Error> {
-    let info = match file_type.as_str() {
-        "csb_memory" => (
-            "Memory",
-            "rgb(54, 162, 235)",
-            "processed_csb",
-            "memory_peak",
-        ),
-        "csb_play_time" => (
-            "Run Time",
-            "rgb(255, 205, 86)",
-            "processed_csb",
-            "player_total_time",
-        ),
-        "ini_memory" => (
-            "Memory",
-            "rgb(54, 162, 235)",
-            "processed_ini",
-            "memory_peak",
-        ),
-        "ini_cut_time" => (
-            "Cut Time",
-            "rgb(255, 159, 64)",
-            "processed_ini",
-            "cutting_time",
-        ),
-        "ini_draw_time" => (
-            "Draw Time",
-            "rgb(75, 192, 192)",
-            "processed_ini",
-            "draw_time",
-        ),
-        _ => {
-            return Err(tower_web::Error::new(
-                "Bad Request",
-                "unexpected graph type",
-                http::StatusCode::BAD_REQUEST,
-            ));
-        }
-    };
-    let conn = db.get().unwrap();
-    let db_data = db_revision_history_for_files(&conn, info.2, info.3, query.r1, query.r2)
-        .map_err(|e| {
-            tower_web::Error::new(
-                "SQL Error",
-                &e.to_string(),
-                http::StatusCode::INTERNAL_SERVER_ERROR,
-            )
-        })?;
-
-    let mut labels = std::collections::HashSet::new();
+) -> error::Result<String> {
+    let (suite, metric) = registry
+        .graph(&file_type)
+        .ok_or_else(|| error::Error::NotFound(format!("unknown graph '{}'", file_type)))?;
+    let aggregation = parse_aggregation(query.agg.as_deref(), settings)?;
+    let conn = db.get()?;
+    let db_data = db_revision_history_for_files(
+        &conn,
+        suite,
+        &metric.column,
+        &query.r1,
+        &query.r2,
+        query.machine.as_deref(),
+        query.branch.as_ref().map_or(MAIN_LINE, String::as_str),
+        aggregation,
+    )?;
+
+    let several_machines = db_data.keys().map(|(_, machine)| machine).unique().count() > 1;
+    let mut revisions = BTreeSet::new();
     let datasets: Vec<_> = db_data
         .into_iter()
-        .map(|(test_name, runs)| {
-            let first_value = runs[0].stat;
+        .map(|(((test_id, test_name), machine), runs)| {
+            // Normalized by the first usable value; a series without one
+            // has no points.
+            let first_value = runs
+                .iter()
+                .map(|r| r.stat)
+                .find(|v| v.is_finite() && *v != 0.0)
+                .unwrap_or(f64::NAN);
             let data: Vec<_> = runs
                 .into_iter()
                 .map(|r| {
-                    labels.insert(r.revision);
-                    json!({"x": r.revision, "y": r.stat / first_value})
+                    let label = revision_label(&r.revision);
+                    revisions.insert((r.sequence, label.clone()));
+                    json!({"x": label, "revision": r.revision, "y": r.stat / first_value})
                 })
                 .collect();
             json!({
-                "label": test_name,
-                "backgroundColor": info.1,
-                "borderColor": info.1,
+                "label": if several_machines {
+                    format!("{} ({})", test_name, machine)
+                } else {
+                    test_name
+                },
+                "test": test_id,
+                "backgroundColor": metric.color,
+                "borderColor": metric.color,
                 "fill": false,
                 "data": data
             })
         })
         .collect();
-    let mut labels = Vec::from_iter(labels.iter());
-    labels.sort();
+    let labels: Vec<_> = revisions.into_iter().map(|(_, label)| label).collect();
     Ok(json!({
         "labels": labels,
         "datasets": datasets
@@ -216,30 +419,79 @@ pub fn api_all_graph_json(
     .to_string())
 }
 
+/// `((test id, test name), machine)`.
+type TestSeries = ((i64, String), String);
+
+/// `((revision, sequence), values)` in the order of the revisions.
+type SeriesRuns = Vec<((String, i64), Vec<f64>)>;
+
 struct RevisionInfo {
-    revision: u32,
+    revision: String,
+    sequence: i64,
     stat: f64,
 }
+#[allow(clippy::too_many_arguments)]
 fn db_revision_history_for_files(
     conn: &Connection,
-    table: &str,
+    suite: &Suite,
     column: &str,
-    low_revision: u32,
-    high_revision: u32,
-) -> rusqlite::Result<HashMap<String, Vec<RevisionInfo>>> {
+    low_revision: &str,
+    high_revision: &str,
+    machine: Option<&str>,
+    branch: &str,
+    aggregation: Aggregation,
+) -> rusqlite::Result<HashMap<TestSeries, Vec<RevisionInfo>>> {
     let mut stmt = conn.prepare_cached(&format!(
-        "SELECT config_file, revision, AVG({}) FROM {} WHERE revision >= ?1 AND revision <= ?2 GROUP BY revision, config_file ORDER BY revision",
-        column, table
+        concat!(
+            "SELECT t.config_file, t.machine, t.revision, r.sequence, t.{} ",
+            "FROM {} t JOIN revisions r ON r.revision = t.revision ",
+            "WHERE r.sequence >= (SELECT sequence FROM revisions WHERE revision = ?1) ",
+            "AND r.sequence <= (SELECT sequence FROM revisions WHERE revision = ?2) ",
+            "AND (?3 IS NULL OR t.machine = ?3) AND t.branch = ?4 ",
+            "AND t.{0} IS NOT NULL AND t.status = 'ok' ",
+            "ORDER BY r.sequence, t.revision"
+        ),
+        column, suite.table
     ))?;
     let results = stmt
-        .query_map(&[&low_revision, &high_revision], |r| {
-            Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+        .query_map(params![low_revision, high_revision, machine, branch], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, String>(1)?,
+                (r.get::<_, String>(2)?, r.get::<_, i64>(3)?),
+                r.get(4)?,
+            ))
         })?
         .filter_map(|r| r.ok());
-    let mut result = HashMap::new();
-    for (config_file, revision, stat) in results {
-        let t = result.entry(config_file).or_insert_with(Vec::new);
-        t.push(RevisionInfo { revision, stat });
+    let aliases = db_aliases(conn, &suite.name)?;
+    let tests = db_suite_tests(conn, &suite.name)?;
+    let mut samples: HashMap<TestSeries, SeriesRuns> = HashMap::new();
+    for (path, machine, revision, value) in results {
+        let test = match tests.get(aliases.resolve(&path, revision.1)) {
+            Some(test) => test.clone(),
+            None => continue,
+        };
+        let runs = samples.entry((test, machine)).or_default();
+        if runs.last().is_some_and(|(r, _)| *r == revision) {
+            if let Some((_, values)) = runs.last_mut() {
+                values.push(value);
+            }
+        } else {
+            runs.push((revision, vec![value]));
+        }
     }
-    Ok(result)
+    Ok(samples
+        .into_iter()
+        .map(|(test, runs)| {
+            let runs = runs
+                .into_iter()
+                .map(|((revision, sequence), values)| RevisionInfo {
+                    revision,
+                    sequence,
+                    stat: aggregation.apply(&values),
+                })
+                .collect();
+            (test, runs)
+        })
+        .collect())
 }
@@ -0,0 +1,232 @@
+use crate::changepoints;
+use crate::comparison::Settings;
+use crate::ingest::{affected_tests, insert_runs, validate_run, Run, RunStatus};
+use crate::metrics::Registry;
+use crate::revisions::Commit;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+pub fn import_file(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
+    path: &Path,
+) -> Result<(), String> {
+    let parsed = match path.extension().and_then(|e| e.to_str()) {
+        Some("csv") => read_csv(path)?,
+        Some("json") | Some("jsonl") | Some("ndjson") => read_json_lines(path)?,
+        _ => {
+            return Err(format!(
+                "{}: expected a .csv or .jsonl file",
+                path.display()
+            ))
+        }
+    };
+
+    let mut runs = Vec::new();
+    let mut rejected = 0;
+    for (line, run) in parsed {
+        match run.and_then(|run| validate_run(registry, &run).map(|_| run)) {
+            Ok(run) => runs.push(run),
+            Err(e) => {
+                eprintln!("{}:{}: {}", path.display(), line, e);
+                rejected += 1;
+            }
+        }
+    }
+
+    let mut conn = db.get().map_err(|e| e.to_string())?;
+    let inserted = insert_runs(&mut conn, registry, settings, &runs).map_err(|e| e.to_string())?;
+    println!("inserted {} rows, rejected {} rows", inserted, rejected);
+    // Importing again would insert the runs twice.
+    if let Err(e) = changepoints::detect(&mut conn, registry, settings, &affected_tests(&runs)) {
+        eprintln!(
+            "error: change point detection failed, run `benchtable detect`: {}",
+            e
+        );
+    }
+    Ok(())
+}
+
+type ParsedRows = Vec<(usize, Result<Run, String>)>;
+
+fn read_json_lines(path: &Path) -> Result<ParsedRows, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, serde_json::from_str(line).map_err(|e| e.to_string())))
+        .collect())
+}
+
+/// Expects a header row with `suite`, `revision` and `config_file` columns,
+/// and optionally `machine`, `branch`, `status`, `error_message`, and the
+/// commit's `sequence`, `commit_time` and space separated `parents`. All
+/// other columns are metric values; empty cells are skipped.
+fn read_csv(path: &Path) -> Result<ParsedRows, String> {
+    let mut reader =
+        csv::Reader::from_path(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .clone();
+    Ok(reader
+        .records()
+        .enumerate()
+        .map(|(i, record)| {
+            let run = record
+                .map_err(|e| e.to_string())
+                .and_then(|record| run_from_record(&headers, &record));
+            (i + 2, run)
+        })
+        .collect())
+}
+
+fn run_from_record(headers: &csv::StringRecord, record: &csv::StringRecord) -> Result<Run, String> {
+    let mut suite = None;
+    let mut revision = None;
+    let mut commit = Commit::default();
+    let mut config_file = None;
+    let mut machine = String::new();
+    let mut branch = String::new();
+    let mut status = RunStatus::Ok;
+    let mut error_message = None;
+    let mut metrics = HashMap::new();
+    for (header, value) in headers.iter().zip(record.iter()) {
+        let value = value.trim();
+        match header.trim() {
+            "suite" => suite = Some(value.to_string()),
+            "revision" => revision = Some(value.to_string()),
+            "sequence" | "commit_time" | "parents" if value.is_empty() => {}
+            "sequence" => {
+                commit.sequence = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid sequence '{}'", value))?,
+                )
+            }
+            "commit_time" => {
+                commit.commit_time = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid commit_time '{}'", value))?,
+                )
+            }
+            "parents" => commit.parents = value.split_whitespace().map(String::from).collect(),
+            "config_file" => config_file = Some(value.to_string()),
+            "machine" => machine = value.to_string(),
+            "branch" => branch = value.to_string(),
+            "status" => status = RunStatus::from_arg(value)?,
+            "error_message" if value.is_empty() => {}
+            "error_message" => error_message = Some(value.to_string()),
+            _ if value.is_empty() => {}
+            metric => {
+                let v = value
+                    .parse()
+                    .map_err(|_| format!("invalid value '{}' for '{}'", value, metric))?;
+                metrics.insert(metric.to_string(), v);
+            }
+        }
+    }
+    Ok(Run {
+        suite: suite.ok_or("missing suite")?,
+        revision: revision.ok_or("missing revision")?,
+        config_file: config_file.ok_or("missing config_file")?,
+        commit,
+        machine,
+        branch,
+        environment: None,
+        status,
+        error_message,
+        metrics,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A file of its own below the system's temp directory.
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("benchtable-import-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_csv_rows_with_line_numbers() {
+        let path = temp_file(
+            "runs.csv",
+            concat!(
+                "suite,revision,sequence,config_file,memory_peak,player_total_time\n",
+                "csb,812345,,a.csb,512,1.5\n",
+                "csb,812345,,b.csb,,2.5\n",
+                "csb,3f9a1c2e,7,c.csb,512,1.5\n",
+                "csb,812345,,d.csb,512,fast\n",
+            ),
+        );
+        let rows = read_csv(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines: Vec<usize> = rows.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, vec![2, 3, 4, 5]);
+        let run = rows[0].1.as_ref().unwrap();
+        assert_eq!(
+            (
+                run.suite.as_str(),
+                run.revision.as_str(),
+                run.config_file.as_str()
+            ),
+            ("csb", "812345", "a.csb")
+        );
+        assert_eq!(run.commit.sequence, None);
+        assert_eq!(run.metrics["memory_peak"], 512.0);
+        // Empty cells are left out, for validation to report.
+        let run = rows[1].1.as_ref().unwrap();
+        assert!(!run.metrics.contains_key("memory_peak"));
+        // Git commits are named by their hash.
+        let run = rows[2].1.as_ref().unwrap();
+        assert_eq!(run.revision, "3f9a1c2e");
+        assert_eq!(run.commit.sequence, Some(7));
+        assert_eq!(
+            rows[3].1.as_ref().err().unwrap(),
+            "invalid value 'fast' for 'player_total_time'"
+        );
+    }
+
+    #[test]
+    fn csv_rows_need_suite_revision_and_config_file() {
+        let headers = csv::StringRecord::from(vec!["suite", "revision", "memory_peak"]);
+        let record = csv::StringRecord::from(vec!["csb", "812345", "512"]);
+        assert_eq!(
+            run_from_record(&headers, &record).err().unwrap(),
+            "missing config_file"
+        );
+    }
+
+    #[test]
+    fn reads_json_lines_skipping_blank_lines() {
+        let path = temp_file(
+            "runs.jsonl",
+            concat!(
+                r#"{"suite": "csb", "revision": 812345, "config_file": "a.csb", "metrics": {"memory_peak": 512}}"#,
+                "\n\n",
+                "{not json}\n",
+            ),
+        );
+        let rows = read_json_lines(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, 1);
+        assert_eq!(rows[0].1.as_ref().unwrap().config_file, "a.csb");
+        assert_eq!(rows[1].0, 3);
+        assert!(rows[1].1.is_err());
+    }
+}
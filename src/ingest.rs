@@ -0,0 +1,347 @@
+use crate::changepoints;
+use crate::comparison::Settings;
+use crate::error;
+use crate::machines::{self, Environment};
+use crate::metrics::Registry;
+use crate::revisions::{self, Commit};
+use crate::testcases;
+use itertools::Itertools;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, ToSql};
+use serde_json::json;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+pub struct RunBatch {
+    runs: Vec<Run>,
+}
+
+#[derive(Deserialize)]
+pub struct Run {
+    pub suite: String,
+    /// SVN revision number or git commit hash.
+    #[serde(deserialize_with = "revisions::deserialize_revision")]
+    pub revision: String,
+    /// Ordering information for revisions that aren't known yet.
+    #[serde(default)]
+    pub commit: Commit,
+    pub config_file: String,
+    /// Identifies the benchmark host; runs of different machines are never pooled.
+    #[serde(default)]
+    pub machine: String,
+    /// Empty for the main line.
+    #[serde(default)]
+    pub branch: String,
+    #[serde(default)]
+    pub environment: Option<Environment>,
+    #[serde(default)]
+    pub status: RunStatus,
+    #[serde(default)]
+    pub error_message: Option<String>,
+    /// Required for successful runs; failed runs may report partial metrics.
+    #[serde(default)]
+    pub metrics: HashMap<String, f64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    #[default]
+    Ok,
+    Failed,
+    Timeout,
+    Crashed,
+}
+
+impl RunStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            RunStatus::Ok => "ok",
+            RunStatus::Failed => "failed",
+            RunStatus::Timeout => "timeout",
+            RunStatus::Crashed => "crashed",
+        }
+    }
+
+    /// Parses the `status` column. Unknown values count as failures.
+    pub fn from_db(status: &str) -> RunStatus {
+        match status {
+            "ok" => RunStatus::Ok,
+            "timeout" => RunStatus::Timeout,
+            "crashed" => RunStatus::Crashed,
+            _ => RunStatus::Failed,
+        }
+    }
+
+    pub fn from_arg(arg: &str) -> Result<RunStatus, String> {
+        match arg {
+            "" | "ok" => Ok(RunStatus::Ok),
+            "failed" => Ok(RunStatus::Failed),
+            "timeout" => Ok(RunStatus::Timeout),
+            "crashed" => Ok(RunStatus::Crashed),
+            other => Err(format!("unknown status '{}'", other)),
+        }
+    }
+}
+
+/// A run that did not produce measurements.
+#[derive(Clone, Debug, Serialize)]
+pub struct Failure {
+    pub status: RunStatus,
+    pub error_message: Option<String>,
+}
+
+impl Failure {
+    pub fn summary(&self) -> String {
+        match &self.error_message {
+            Some(message) => format!("{}: {}", self.status.name(), message),
+            None => self.status.name().to_string(),
+        }
+    }
+}
+
+pub fn api_post_runs(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
+    batch: RunBatch,
+) -> error::Result<String> {
+    for (i, run) in batch.runs.iter().enumerate() {
+        validate_run(registry, run)
+            .map_err(|e| error::Error::BadRequest(format!("run {}: {}", i, e)))?;
+    }
+
+    let mut conn = db.get()?;
+    let inserted = insert_runs(&mut conn, registry, settings, &batch.runs)?;
+    // The runs are stored at this point. Failing the request would make
+    // clients retry and insert them twice; `benchtable detect` catches up.
+    let tests = affected_tests(&batch.runs);
+    if let Err(e) = changepoints::detect(&mut conn, registry, settings, &tests) {
+        eprintln!("error: change point detection failed: {}", e);
+    }
+
+    Ok(json!({ "inserted": inserted }).to_string())
+}
+
+pub fn validate_run(registry: &Registry, run: &Run) -> Result<(), String> {
+    let suite = registry
+        .suite(&run.suite)
+        .ok_or_else(|| format!("unknown suite '{}'", run.suite))?;
+    if run.revision.is_empty() {
+        return Err("revision is empty".to_string());
+    }
+    if let Some(revision) = std::iter::once(&run.revision)
+        .chain(run.commit.parents.iter())
+        .find(|r| !revisions::is_valid_revision(r))
+    {
+        return Err(format!(
+            "invalid revision '{}', expected letters, digits, '.', '_' or '-'",
+            revision
+        ));
+    }
+    if run.config_file.is_empty() {
+        return Err("config_file is empty".to_string());
+    }
+    for column in suite.metrics.iter().map(|m| &m.column) {
+        match run.metrics.get(column) {
+            Some(v) if v.is_finite() => {}
+            Some(_) => return Err(format!("metric '{}' is not a finite number", column)),
+            None if run.status != RunStatus::Ok => {}
+            None => return Err(format!("missing metric '{}'", column)),
+        }
+    }
+    if run.status == RunStatus::Ok && run.error_message.is_some() {
+        return Err("error_message given for a successful run".to_string());
+    }
+    if let Some(unknown) = run
+        .metrics
+        .keys()
+        .find(|k| !suite.metrics.iter().any(|m| &m.column == *k))
+    {
+        return Err(format!(
+            "unknown metric '{}' for suite '{}'",
+            unknown, run.suite
+        ));
+    }
+    Ok(())
+}
+
+/// Inserts all runs in a single transaction. Runs must have passed `validate_run`.
+pub fn insert_runs(
+    conn: &mut Connection,
+    registry: &Registry,
+    settings: &Settings,
+    runs: &[Run],
+) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    for run in runs {
+        let suite = registry.suite(&run.suite).expect("run was not validated");
+        let query = format!(
+            concat!(
+                "INSERT INTO {} ",
+                "(revision, config_file, machine, branch, status, error_message, {}) ",
+                "VALUES (?1, ?2, ?3, ?4, ?5, ?6, {})"
+            ),
+            suite.table,
+            suite.metrics.iter().map(|m| &m.column).join(", "),
+            (0..suite.metrics.len())
+                .map(|i| format!("?{}", i + 7))
+                .join(", ")
+        );
+        let status = run.status.name();
+        let values: Vec<Option<f64>> = suite
+            .metrics
+            .iter()
+            .map(|m| run.metrics.get(&m.column).cloned())
+            .collect();
+        let mut params: Vec<&dyn ToSql> = vec![
+            &run.revision,
+            &run.config_file,
+            &run.machine,
+            &run.branch,
+            &status,
+            &run.error_message,
+        ];
+        for value in values.iter() {
+            params.push(value);
+        }
+        tx.prepare_cached(&query)?.execute(&params)?;
+        testcases::db_register(&tx, registry, &suite.name, &run.config_file)?;
+        revisions::db_record_revision(
+            &tx,
+            &run.revision,
+            &run.branch,
+            &run.commit,
+            settings.lowest_revision,
+        )?;
+        if let Some(environment) = &run.environment {
+            machines::db_update_environment(&tx, &run.machine, environment)?;
+        }
+    }
+    tx.commit()?;
+    Ok(runs.len())
+}
+
+/// Distinct `(suite, config_file)` pairs of the given runs.
+pub fn affected_tests(runs: &[Run]) -> Vec<(String, String)> {
+    runs.iter()
+        .map(|run| (run.suite.clone(), run.config_file.clone()))
+        .unique()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+    use rusqlite::NO_PARAMS;
+
+    fn run(suite: &str, metrics: &[(&str, f64)]) -> Run {
+        Run {
+            suite: suite.to_string(),
+            revision: "812345".to_string(),
+            commit: Commit::default(),
+            config_file: "a.csb".to_string(),
+            machine: String::new(),
+            branch: String::new(),
+            environment: None,
+            status: RunStatus::Ok,
+            error_message: None,
+            metrics: metrics.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+        }
+    }
+
+    fn csb_run() -> Run {
+        run("csb", &[("memory_peak", 512.0), ("player_total_time", 1.5)])
+    }
+
+    #[test]
+    fn accepts_complete_runs() {
+        assert_eq!(
+            validate_run(&Registry::new(Vec::new(), Vec::new()).unwrap(), &csb_run()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_runs() {
+        let mut empty_path = csb_run();
+        empty_path.config_file.clear();
+        assert_eq!(
+            validate_run(&Registry::new(Vec::new(), Vec::new()).unwrap(), &empty_path),
+            Err("config_file is empty".to_string())
+        );
+        assert_eq!(
+            validate_run(&Registry::new(Vec::new(), Vec::new()).unwrap(), &run("xyz", &[])),
+            Err("unknown suite 'xyz'".to_string())
+        );
+        assert_eq!(
+            validate_run(
+                &Registry::new(Vec::new(), Vec::new()).unwrap(),
+                &run("csb", &[("memory_peak", 512.0)])
+            ),
+            Err("missing metric 'player_total_time'".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_bad_metrics() {
+        let mut infinite = csb_run();
+        infinite
+            .metrics
+            .insert("memory_peak".to_string(), f64::INFINITY);
+        assert_eq!(
+            validate_run(&Registry::new(Vec::new(), Vec::new()).unwrap(), &infinite),
+            Err("metric 'memory_peak' is not a finite number".to_string())
+        );
+        let mut unknown = csb_run();
+        unknown.metrics.insert("draw_time".to_string(), 1.0);
+        assert_eq!(
+            validate_run(&Registry::new(Vec::new(), Vec::new()).unwrap(), &unknown),
+            Err("unknown metric 'draw_time' for suite 'csb'".to_string())
+        );
+    }
+
+    #[test]
+    fn failed_runs_may_omit_metrics() {
+        let mut crashed = run("csb", &[("memory_peak", 512.0)]);
+        crashed.status = RunStatus::Crashed;
+        crashed.error_message = Some("segfault".to_string());
+        assert_eq!(
+            validate_run(&Registry::new(Vec::new(), Vec::new()).unwrap(), &crashed),
+            Ok(())
+        );
+        let mut message = csb_run();
+        message.error_message = Some("segfault".to_string());
+        assert_eq!(
+            validate_run(&Registry::new(Vec::new(), Vec::new()).unwrap(), &message),
+            Err("error_message given for a successful run".to_string())
+        );
+    }
+
+    #[test]
+    fn inserts_runs_into_their_suite_table() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let registry = Registry::new(Vec::new(), Vec::new()).unwrap();
+        schema::migrate(&mut conn).unwrap();
+        schema::ensure_suites(&conn, &registry).unwrap();
+        assert_eq!(
+            insert_runs(
+                &mut conn,
+                &registry,
+                &Settings::default(),
+                &[csb_run(), csb_run()]
+            ),
+            Ok(2)
+        );
+        let (count, time): (i64, f64) = conn
+            .query_row(
+                "SELECT COUNT(*), MAX(player_total_time) FROM processed_csb",
+                NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((count, time), (2, 1.5));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::aliases::db_aliases;
+use crate::error;
+use crate::metrics::{Registry, Suite};
+use itertools::Itertools;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, ToSql, NO_PARAMS};
+use serde_json::json;
+
+/// Description of a benchmark host, reported alongside its runs.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Environment {
+    pub hostname: Option<String>,
+    pub cpu: Option<String>,
+    pub os: Option<String>,
+    pub build_config: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Machine {
+    pub name: String,
+    #[serde(flatten)]
+    pub environment: Environment,
+}
+
+pub fn api_machines_json(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+) -> error::Result<String> {
+    let conn = db.get()?;
+    let machines = db_machines(&conn, registry).and_then(|names| {
+        names
+            .into_iter()
+            .map(|name| {
+                let environment = db_environment(&conn, &name)?;
+                Ok(Machine { name, environment })
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()
+    })?;
+    Ok(json!({ "machines": machines }).to_string())
+}
+
+/// All machines that reported runs, sorted by name. Runs recorded before
+/// machines were tracked belong to the machine named "".
+pub fn db_machines(conn: &Connection, registry: &Registry) -> rusqlite::Result<Vec<String>> {
+    let query = registry
+        .suites
+        .iter()
+        .map(|suite| format!("SELECT machine FROM {}", suite.table))
+        .join(" UNION ")
+        + " ORDER BY machine";
+    Ok(conn
+        .prepare(&query)?
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .filter_map(|r| r.ok())
+        .collect())
+}
+
+/// The machine a query refers to if it doesn't name one, so that results of
+/// different machines are never pooled.
+pub fn default_machine(conn: &Connection, registry: &Registry) -> rusqlite::Result<String> {
+    Ok(db_machines(conn, registry)?
+        .into_iter()
+        .next()
+        .unwrap_or_default())
+}
+
+/// Whether `machine` ran any test of `revision` on `branch`.
+pub fn db_has_runs(
+    conn: &Connection,
+    registry: &Registry,
+    machine: &str,
+    branch: &str,
+    revision: &str,
+) -> rusqlite::Result<bool> {
+    let query = registry
+        .suites
+        .iter()
+        .map(|suite| {
+            format!(
+                "SELECT 1 FROM {} WHERE machine = ?1 AND branch = ?2 AND revision = ?3",
+                suite.table
+            )
+        })
+        .join(" UNION ALL ");
+    let mut stmt = conn.prepare(&format!("SELECT EXISTS ({})", query))?;
+    stmt.query_row(params![machine, branch, revision], |row| row.get(0))
+}
+
+/// `(machine, branch)` pairs that ran the test, also under the paths it had
+/// before being renamed. Each pair is a history of its own.
+pub fn db_test_series(
+    conn: &Connection,
+    suite: &Suite,
+    config_file: &str,
+) -> rusqlite::Result<Vec<(String, String)>> {
+    let aliases = db_aliases(conn, &suite.name)?;
+    let sources = aliases.sources(config_file);
+    let mut stmt = conn.prepare_cached(&format!(
+        concat!(
+            "SELECT DISTINCT t.machine, t.branch, t.config_file, r.sequence ",
+            "FROM {} t JOIN revisions r ON r.revision = t.revision ",
+            "WHERE t.config_file IN ({}) ",
+            "ORDER BY t.machine, t.branch"
+        ),
+        suite.table,
+        (1..=sources.len()).map(|i| format!("?{}", i)).join(", ")
+    ))?;
+    let params: Vec<&dyn ToSql> = sources.iter().map(|s| s as &dyn ToSql).collect();
+    let series = stmt
+        .query_map(&params, |row| {
+            Ok((
+                (row.get(0)?, row.get(1)?),
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?
+        .filter_map(|r| r.ok())
+        .filter(|(_, path, sequence)| aliases.resolve(path, *sequence) == config_file)
+        .map(|(series, _, _)| series)
+        .dedup()
+        .collect();
+    Ok(series)
+}
+
+fn db_environment(conn: &Connection, name: &str) -> rusqlite::Result<Environment> {
+    let mut stmt = conn
+        .prepare_cached("SELECT hostname, cpu, os, build_config FROM machines WHERE name = ?1")?;
+    let mut rows = stmt.query_map(&[&name], |row| {
+        Ok(Environment {
+            hostname: row.get(0)?,
+            cpu: row.get(1)?,
+            os: row.get(2)?,
+            build_config: row.get(3)?,
+        })
+    })?;
+    Ok(rows.next().and_then(|r| r.ok()).unwrap_or_default())
+}
+
+/// Records the latest known environment of a machine.
+pub fn db_update_environment(
+    conn: &Connection,
+    name: &str,
+    environment: &Environment,
+) -> rusqlite::Result<()> {
+    conn.prepare_cached(concat!(
+        "INSERT OR REPLACE INTO machines (name, hostname, cpu, os, build_config) ",
+        "VALUES (?1, ?2, ?3, ?4, ?5)"
+    ))?
+    .execute(params![
+        name,
+        environment.hostname,
+        environment.cpu,
+        environment.os,
+        environment.build_config
+    ])?;
+    Ok(())
+}
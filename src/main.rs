@@ -2,82 +2,419 @@
 extern crate tower_web;
 
 use flate2::Compression;
-use r2d2::Pool;
+use itertools::Itertools;
+use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use std::{io, path::PathBuf};
-use tokio::{fs::File, prelude::Future};
+use std::{path::Path, sync::Arc};
 use tower_web::middleware::deflate::DeflateMiddleware;
 use tower_web::ServiceBuilder;
 
+mod aliases;
+mod assets;
+mod branches;
+mod changepoints;
+mod check;
+mod commits;
 mod comparison;
+mod config;
+mod error;
 mod graphs;
-
-pub static LOWEST_REVISION: u32 = 800_000;
+mod import;
+mod ingest;
+mod machines;
+mod metrics;
+mod noise;
+mod report;
+mod revisions;
+mod schema;
+mod stats;
+mod test_detail;
+mod testcases;
 
 #[derive(Clone, Debug)]
 pub struct TowerWeb {
     db_pool: Pool<SqliteConnectionManager>,
+    registry: Arc<metrics::Registry>,
+    settings: comparison::Settings,
+    commit_settings: Arc<commits::CommitSettings>,
+    assets: Arc<assets::Assets>,
 }
 
 impl_web! {
     impl TowerWeb {
-        pub fn new(db_pool: Pool<SqliteConnectionManager>) -> Self {
-            Self { db_pool }
+        pub fn new(
+            db_pool: Pool<SqliteConnectionManager>,
+            registry: Arc<metrics::Registry>,
+            settings: comparison::Settings,
+            commit_settings: Arc<commits::CommitSettings>,
+            assets: Arc<assets::Assets>,
+        ) -> Self {
+            Self { db_pool, registry, settings, commit_settings, assets }
         }
 
+        // Queries and bodies are taken raw and parsed by the routes, so that
+        // malformed ones get error bodies of the route's type.
+
         #[get("/")]
-        #[content_type("text/html")]
-        fn index(&self, query_string: comparison::IndexQuery) -> Result<String, tower_web::Error> {
-            comparison::index(&self.db_pool, query_string)
+        fn index(&self, query_string: Option<String>) -> Result<http::Response<String>, tower_web::Error> {
+            error::respond(error::parse_query(query_string).and_then(|query| comparison::index(&self.db_pool, &self.registry, &self.settings, &self.commit_settings, query)), error::HTML)
+        }
+
+        #[get("/test/:id")]
+        fn test(&self, id: String, query_string: Option<String>) -> Result<http::Response<String>, tower_web::Error> {
+            let page = error::parse_id(&id).and_then(|id| {
+                let query = error::parse_query(query_string)?;
+                test_detail::test_page(&self.db_pool, &self.registry, &self.settings, &self.commit_settings, id, query)
+            });
+            error::respond(page, error::HTML)
+        }
+
+        #[get("/api/compare")]
+        fn api_compare(&self, query_string: Option<String>) -> Result<http::Response<String>, tower_web::Error> {
+            error::respond(error::parse_query(query_string).and_then(|query| comparison::api_compare_json(&self.db_pool, &self.registry, &self.settings, query)), error::JSON)
+        }
+
+        #[get("/report.md")]
+        fn report_md(&self, query_string: Option<String>) -> Result<http::Response<String>, tower_web::Error> {
+            error::respond(error::parse_query(query_string).and_then(|query| report::report_markdown(&self.db_pool, &self.registry, &self.settings, query)), error::MARKDOWN)
         }
 
         #[get("/api/file/:file_type")]
-        #[content_type("text/json")]
-        fn api_file(&self, file_type: String, query_string: graphs::FileGraphQuery) -> Result<String, tower_web::Error> {
-            graphs::api_file_graph_json(&self.db_pool, file_type, query_string)
+        fn api_file(&self, file_type: String, query_string: Option<String>) -> Result<http::Response<String>, tower_web::Error> {
+            error::respond(error::parse_query(query_string).and_then(|query| graphs::api_file_graph_json(&self.db_pool, &self.registry, &self.settings, file_type, query)), error::JSON)
         }
 
         #[get("/api/all/:file_type")]
-        #[content_type("text/json")]
-        fn api_all(&self, file_type: String, query_string: graphs::AllGraphQuery) -> Result<String, tower_web::Error> {
-            graphs::api_all_graph_json(&self.db_pool, file_type, query_string)
+        fn api_all(&self, file_type: String, query_string: Option<String>) -> Result<http::Response<String>, tower_web::Error> {
+            error::respond(error::parse_query(query_string).and_then(|query| graphs::api_all_graph_json(&self.db_pool, &self.registry, &self.settings, file_type, query)), error::JSON)
+        }
+
+        #[get("/api/changepoints")]
+        fn api_changepoints(&self, query_string: Option<String>) -> Result<http::Response<String>, tower_web::Error> {
+            error::respond(error::parse_query(query_string).and_then(|query| changepoints::api_changepoints_json(&self.db_pool, query)), error::JSON)
+        }
+
+        #[get("/api/machines")]
+        fn api_machines(&self) -> Result<http::Response<String>, tower_web::Error> {
+            error::respond(machines::api_machines_json(&self.db_pool, &self.registry), error::JSON)
+        }
+
+        #[post("/api/runs")]
+        fn api_runs(&self, body: Vec<u8>) -> Result<http::Response<String>, tower_web::Error> {
+            error::respond(error::parse_json(&body).and_then(|batch| ingest::api_post_runs(&self.db_pool, &self.registry, &self.settings, batch)), error::JSON)
         }
 
         #[get("/static/*rel_path")]
-        fn static_files(&self, rel_path: PathBuf) -> impl Future<Item = File, Error = io::Error> {
-            let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-            path.push("static");
-            path.push(rel_path);
-            File::open(path)
+        fn static_files(
+            &self,
+            rel_path: String,
+            if_none_match: Option<String>,
+        ) -> Result<http::Response<assets::Body>, tower_web::Error> {
+            let response = self.assets.response(Path::new(&rel_path), if_none_match.as_deref());
+            error::respond_with(response, error::TEXT).map(|response| response.map(assets::stream))
+        }
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let config = config::load(&mut args).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(2);
+    });
+    let db_path = config.sqlite_db;
+    let registry = Arc::new(
+        metrics::Registry::new(config.suites, config.tests.name_rules).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }),
+    );
+    let manager = r2d2_sqlite::SqliteConnectionManager::file(&db_path);
+    let pool = r2d2::Pool::new(manager).unwrap_or_else(|e| {
+        eprintln!("error: {}: {}", db_path.display(), e);
+        std::process::exit(1);
+    });
+
+    let applied = schema::migrate(&mut connection(&pool)).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    schema::ensure_suites(&connection(&pool), &registry).unwrap_or_else(|e| {
+        eprintln!("error: creating suite tables failed: {}", e);
+        std::process::exit(1);
+    });
+    testcases::db_register_all(&mut connection(&pool), &registry).unwrap_or_else(|e| {
+        eprintln!("error: registering tests failed: {}", e);
+        std::process::exit(1);
+    });
+
+    match args.get(1).map(String::as_str) {
+        None | Some("serve") => serve(
+            pool,
+            registry,
+            config.comparison,
+            config.commits,
+            &config.server,
+        ),
+        Some("import") => {
+            let file = args.get(2).unwrap_or_else(|| usage());
+            if let Err(e) =
+                import::import_file(&pool, &registry, &config.comparison, Path::new(file))
+            {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("import-log") => {
+            let file = args.get(2).unwrap_or_else(|| usage());
+            if let Err(e) = commits::import_log(&pool, config.comparison.lowest_revision, file) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("detect") => {
+            let found =
+                changepoints::detect_all(&mut connection(&pool), &registry, &config.comparison)
+                    .unwrap_or_else(|e| {
+                        eprintln!("error: {}", e);
+                        std::process::exit(1);
+                    });
+            println!("found {} change points", found);
         }
+        Some("check") => {
+            let base = revision_flag(&args, "--base");
+            let head = revision_flag(&args, "--head");
+            let conn = connection(&pool);
+            let machine = required_machine_flag(&conn, &registry, &args);
+            let branch = flag(&args, "--branch").unwrap_or(branches::MAIN_LINE);
+            let passed = check::check(
+                &conn,
+                &registry,
+                &config.comparison,
+                &config.check,
+                &machine,
+                branch,
+                base,
+                head,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(2);
+            });
+            if !passed {
+                std::process::exit(1);
+            }
+        }
+        Some("report") => {
+            let r1 = revision_flag(&args, "--r1");
+            let r2 = revision_flag(&args, "--r2");
+            let format = report::Format::from_arg(flag(&args, "--format")).unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(2);
+            });
+            let conn = connection(&pool);
+            let machine = machine_flag(&conn, &registry, &args);
+            let branch = flag(&args, "--branch").unwrap_or(branches::MAIN_LINE);
+            let report = report::report_for_revisions(
+                &conn,
+                &registry,
+                &config.comparison,
+                &machine,
+                branch,
+                r1,
+                r2,
+                format,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            });
+            print!("{}", report);
+        }
+        Some("alias") => {
+            let alias = aliases::Alias {
+                suite: args.get(2).unwrap_or_else(|| usage()).clone(),
+                old_path: args.get(3).unwrap_or_else(|| usage()).clone(),
+                new_path: args.get(4).unwrap_or_else(|| usage()).clone(),
+                revision: revision_flag(&args, "--from").to_string(),
+            };
+            if let Err(e) = aliases::add_alias(&pool, &registry, &config.comparison, &alias) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            println!("{}", aliases::describe(&alias));
+        }
+        Some("unalias") => {
+            let suite = args.get(2).unwrap_or_else(|| usage());
+            let old_path = args.get(3).unwrap_or_else(|| usage());
+            if let Err(e) =
+                aliases::remove_alias(&pool, &registry, &config.comparison, suite, old_path)
+            {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("aliases") => {
+            let all = aliases::db_all_aliases(&connection(&pool)).unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            });
+            for alias in all.iter() {
+                println!("{}", aliases::describe(alias));
+            }
+        }
+        Some("threshold") => {
+            let suite = args.get(2).unwrap_or_else(|| usage());
+            let path = args.get(3).unwrap_or_else(|| usage());
+            let threshold = match args.get(4).map(String::as_str) {
+                Some("auto") => None,
+                Some(value) => Some(value.parse().unwrap_or_else(|_| usage())),
+                None => usage(),
+            };
+            let metric = flag(&args, "--metric");
+            if let Err(e) = noise::set_override(&pool, &registry, suite, path, metric, threshold) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("migrate") => {
+            let version = schema::schema_version(&connection(&pool)).unwrap_or_else(|e| {
+                eprintln!("error: {}", error::Error::from(e));
+                std::process::exit(1);
+            });
+            println!(
+                "applied {} migrations, schema is at version {}",
+                applied, version
+            );
+        }
+        Some(_) => usage(),
     }
 }
 
-#[derive(Deserialize)]
-struct Config {
-    sqlite_db: String,
+fn usage() -> ! {
+    eprintln!("usage: benchtable [options] [serve]");
+    eprintln!("       benchtable import <file.csv|file.jsonl>");
+    eprintln!("       benchtable import-log <svn-log.xml|git-log.txt|->");
+    eprintln!("       benchtable migrate");
+    eprintln!("       benchtable detect");
+    eprintln!("       benchtable alias <suite> <old-path> <new-path> --from <revision>");
+    eprintln!("       benchtable unalias <suite> <old-path>");
+    eprintln!("       benchtable aliases");
+    eprintln!("       benchtable threshold <suite> <test-path> <value|auto> [--metric <name>]");
+    eprintln!(
+        "       benchtable check --base <revision> --head <revision> [--machine <name>] [--branch <name>]"
+    );
+    eprintln!(
+        "       benchtable report --r1 <revision> --r2 <revision> [--format md|text] [--machine <name>] [--branch <name>]"
+    );
+    eprintln!();
+    eprintln!("options:");
+    eprintln!("  --config <path>  config file, default config.toml (BENCHTABLE_CONFIG)");
+    for (flag, variable) in config::OVERRIDES.iter() {
+        eprintln!(
+            "  {} <value>  overrides the config file ({})",
+            flag, variable
+        );
+    }
+    std::process::exit(2);
 }
 
-fn load_config() -> PathBuf {
-    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    path.push("config.toml");
-    let config: String = std::fs::read_to_string(path).unwrap();
-    let config: Config = toml::from_str(&config).unwrap();
-    PathBuf::from(config.sqlite_db)
+/// Value following `name` on the command line.
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
 }
 
-fn main() {
-    let db_path = load_config();
+/// An SVN revision number or git commit hash.
+fn revision_flag<'a>(args: &'a [String], name: &str) -> &'a str {
+    match flag(args, name) {
+        Some(revision) if !revision.is_empty() && !revision.starts_with("--") => revision,
+        Some(_) => {
+            eprintln!("error: {} expects a revision", name);
+            std::process::exit(2);
+        }
+        None => usage(),
+    }
+}
 
-    let addr = "127.0.0.1:8000".parse().expect("Invalid IP");
-    println!("Listening on http://{}", addr);
+/// The `--machine` flag, defaulting to the first machine with runs.
+/// A connection for a command; the commands run one at a time, so this only
+/// fails if the database became unusable.
+fn connection(pool: &Pool<SqliteConnectionManager>) -> PooledConnection<SqliteConnectionManager> {
+    pool.get().unwrap_or_else(|e| {
+        eprintln!("error: {}", error::Error::from(e));
+        std::process::exit(1);
+    })
+}
+
+fn machine_flag(
+    conn: &rusqlite::Connection,
+    registry: &metrics::Registry,
+    args: &[String],
+) -> String {
+    match flag(args, "--machine") {
+        Some(machine) => machine.to_string(),
+        None => machines::default_machine(conn, registry).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }),
+    }
+}
 
-    let manager = r2d2_sqlite::SqliteConnectionManager::file(db_path);
-    let pool = r2d2::Pool::new(manager).unwrap();
+/// The `--machine` flag, which may only be left out if a single machine has
+/// runs. A gate must not compare whichever machine happens to sort first.
+fn required_machine_flag(
+    conn: &rusqlite::Connection,
+    registry: &metrics::Registry,
+    args: &[String],
+) -> String {
+    if let Some(machine) = flag(args, "--machine") {
+        return machine.to_string();
+    }
+    let machines = machines::db_machines(conn, registry).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(2);
+    });
+    match machines.as_slice() {
+        [] => String::new(),
+        [machine] => machine.clone(),
+        _ => {
+            eprintln!(
+                "error: --machine is required, runs are from {}",
+                machines.iter().map(|m| format!("'{}'", m)).join(", ")
+            );
+            std::process::exit(2);
+        }
+    }
+}
+
+fn serve(
+    pool: Pool<SqliteConnectionManager>,
+    registry: Arc<metrics::Registry>,
+    settings: comparison::Settings,
+    commit_settings: commits::CommitSettings,
+    server: &config::ServerSettings,
+) {
+    let addr = server.address().unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(2);
+    });
+    println!("Listening on http://{}", addr);
 
     ServiceBuilder::new()
-        .resource(TowerWeb::new(pool))
+        .resource(TowerWeb::new(
+            pool,
+            registry,
+            settings,
+            Arc::new(commit_settings),
+            Arc::new(assets::Assets::new(server.static_dir.clone())),
+        ))
         .middleware(DeflateMiddleware::new(Compression::fast()))
         .run(&addr)
-        .unwrap();
+        .unwrap_or_else(|e| {
+            eprintln!("error: {}: {}", addr, e);
+            std::process::exit(1);
+        });
 }
@@ -0,0 +1,193 @@
+use crate::schema;
+use crate::testcases::{canonical_name, NameRule};
+
+/// A group of tests stored in one table, e.g. all `.csb` files.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Suite {
+    /// Short identifier used in URLs, e.g. `csb`.
+    pub name: String,
+    pub title: String,
+    pub table: String,
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Metric {
+    /// Short identifier used in URLs and as the column label, e.g. `mem`.
+    pub name: String,
+    pub column: String,
+    pub title: String,
+    pub unit: String,
+    pub color: String,
+    #[serde(default = "default_lower_is_better")]
+    pub lower_is_better: bool,
+}
+
+fn default_lower_is_better() -> bool {
+    true
+}
+
+#[derive(Clone, Debug)]
+pub struct Registry {
+    pub suites: Vec<Suite>,
+    /// Turn test paths into display names.
+    pub name_rules: Vec<NameRule>,
+}
+
+impl Registry {
+    /// Uses the suites declared in `config.toml`, or the built-in CSB/INI
+    /// suites if there are none.
+    pub fn new(suites: Vec<Suite>, name_rules: Vec<NameRule>) -> Result<Self, String> {
+        let suites = if suites.is_empty() {
+            Self::builtin()
+        } else {
+            suites
+        };
+        validate_suites(&suites)?;
+        Ok(Self { suites, name_rules })
+    }
+
+    fn builtin() -> Vec<Suite> {
+        let memory = Metric {
+            name: "mem".to_string(),
+            column: "memory_peak".to_string(),
+            title: "Memory".to_string(),
+            unit: "MB".to_string(),
+            color: "rgb(54, 162, 235)".to_string(),
+            lower_is_better: true,
+        };
+        let time = |name: &str, column: &str, title: &str, color: &str| Metric {
+            name: name.to_string(),
+            column: column.to_string(),
+            title: title.to_string(),
+            unit: "s".to_string(),
+            color: color.to_string(),
+            lower_is_better: true,
+        };
+        vec![
+            Suite {
+                name: "csb".to_string(),
+                title: "CSB Benchmarks".to_string(),
+                table: "processed_csb".to_string(),
+                metrics: vec![
+                    time("time", "player_total_time", "Run Time", "rgb(255, 205, 86)"),
+                    memory.clone(),
+                ],
+            },
+            Suite {
+                name: "ini".to_string(),
+                title: "INI Benchmarks".to_string(),
+                table: "processed_ini".to_string(),
+                metrics: vec![
+                    time("cut", "cutting_time", "Cut Time", "rgb(255, 159, 64)"),
+                    time("draw", "draw_time", "Draw Time", "rgb(75, 192, 192)"),
+                    memory,
+                ],
+            },
+        ]
+    }
+
+    pub fn suite(&self, name: &str) -> Option<&Suite> {
+        self.suites.iter().find(|s| s.name == name)
+    }
+
+    /// Looks up a summary graph id of the form `<suite>_<metric>`.
+    pub fn graph(&self, id: &str) -> Option<(&Suite, &Metric)> {
+        self.suites.iter().find_map(|suite| {
+            suite
+                .metrics
+                .iter()
+                .find(|m| id == format!("{}_{}", suite.name, m.name))
+                .map(|metric| (suite, metric))
+        })
+    }
+
+    pub fn test_name(&self, path: &str) -> String {
+        canonical_name(&self.name_rules, path)
+    }
+
+    /// Distinct metric titles, in declaration order. Used as sort options.
+    pub fn metric_titles(&self) -> Vec<&str> {
+        let mut titles: Vec<&str> = Vec::new();
+        for metric in self.suites.iter().flat_map(|s| s.metrics.iter()) {
+            if !titles.contains(&metric.title.as_str()) {
+                titles.push(&metric.title);
+            }
+        }
+        titles
+    }
+}
+
+/// Table and column names are spliced into SQL, and suite and metric names
+/// into URLs and graph ids, so all of them must be plain identifiers. Metric
+/// columns must not shadow the columns every suite table has.
+pub fn validate_suites(suites: &[Suite]) -> Result<(), String> {
+    let mut graphs: Vec<String> = Vec::new();
+    for (i, suite) in suites.iter().enumerate() {
+        check_identifier("suite name", &suite.name)?;
+        check_identifier("table", &suite.table)?;
+        if let Some(other) = suites[..i]
+            .iter()
+            .find(|s| s.name == suite.name || s.table.eq_ignore_ascii_case(&suite.table))
+        {
+            return Err(format!(
+                "suites '{}' and '{}' share a name or table",
+                other.name, suite.name
+            ));
+        }
+        for (j, metric) in suite.metrics.iter().enumerate() {
+            check_identifier("metric name", &metric.name)?;
+            check_identifier("column", &metric.column)?;
+            if schema::is_reserved_column(&metric.column) {
+                return Err(format!(
+                    "suite '{}': column '{}' is reserved",
+                    suite.name, metric.column
+                ));
+            }
+            if suite.metrics[..j]
+                .iter()
+                .any(|m| m.name == metric.name || m.column.eq_ignore_ascii_case(&metric.column))
+            {
+                return Err(format!(
+                    "suite '{}': metric '{}' repeats a name or column",
+                    suite.name, metric.name
+                ));
+            }
+            let graph = format!("{}_{}", suite.name, metric.name);
+            if graphs.contains(&graph) {
+                return Err(format!(
+                    "suite '{}': graph id '{}' is already taken by another suite",
+                    suite.name, graph
+                ));
+            }
+            graphs.push(graph);
+        }
+    }
+    Ok(())
+}
+
+fn check_identifier(what: &str, name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid {} '{}', expected letters, digits and '_'",
+            what, name
+        ))
+    }
+}
+
+impl Metric {
+    pub fn format(&self, v: f64) -> String {
+        match self.unit.as_str() {
+            "s" => format!("{:.2}s", v),
+            "MB" => format!("{:.0} MB", v),
+            unit => format!("{:.2} {}", v, unit),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::comparison::Settings;
+use crate::graphs::RevisionInfos;
+use crate::metrics::{Registry, Suite};
+use crate::stats;
+use crate::testcases::db_test_id;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+
+/// Fewer measured revisions give no estimate, and the constant threshold
+/// applies.
+const MIN_SAMPLES: usize = 5;
+
+/// Relative noise of a metric's recent history, e.g. 0.02 if the aggregated
+/// values typically scatter by 2% around their level.
+pub fn relative_noise(values: &[f64]) -> Option<f64> {
+    if values.len() < MIN_SAMPLES {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let level = stats::median(&sorted);
+    let noise = stats::difference_sigma(values) / level.abs();
+    if noise.is_finite() {
+        Some(noise)
+    } else {
+        None
+    }
+}
+
+pub fn db_clear(conn: &Connection, test_id: i64) -> rusqlite::Result<()> {
+    conn.prepare_cached("DELETE FROM noise WHERE test_id = ?1")?
+        .execute(&[&test_id])?;
+    Ok(())
+}
+
+/// Estimates the noise of every metric from the last `noise_window`
+/// measured revisions of one `(machine, branch)` history.
+pub fn db_update(
+    conn: &Connection,
+    suite: &Suite,
+    test_id: i64,
+    machine: &str,
+    branch: &str,
+    history: &[RevisionInfos],
+    settings: &Settings,
+) -> rusqlite::Result<()> {
+    for (i, metric) in suite.metrics.iter().enumerate() {
+        let mut values: Vec<f64> = history
+            .iter()
+            .rev()
+            .map(|r| r.stats[i])
+            .filter(|v| v.is_finite())
+            .take(settings.noise_window)
+            .collect();
+        values.reverse();
+        if let Some(noise) = relative_noise(&values) {
+            conn.prepare_cached(concat!(
+                "INSERT OR REPLACE INTO noise (test_id, machine, branch, metric, noise, samples) ",
+                "VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+            ))?
+            .execute(params![
+                test_id,
+                machine,
+                branch,
+                metric.name,
+                noise,
+                values.len() as i64
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+/// Regression thresholds of the tests of one suite, for a comparison of two
+/// `(machine, branch)` sides.
+pub struct Thresholds {
+    /// Larger noise of both sides, by `(test id, metric)`.
+    noise: HashMap<(i64, String), f64>,
+    overrides: HashMap<(i64, String), f64>,
+}
+
+impl Thresholds {
+    /// A manual override, or a multiple of the estimated noise, but at least
+    /// `min_threshold`. Without an estimate, the constant threshold.
+    pub fn threshold(&self, settings: &Settings, test_id: i64, metric: &str) -> f64 {
+        let key = (test_id, metric.to_string());
+        if let Some(threshold) = self.overrides.get(&key) {
+            return *threshold;
+        }
+        match self.noise.get(&key) {
+            Some(noise) => (settings.noise_multiplier * noise).max(settings.min_threshold),
+            None => settings.threshold,
+        }
+    }
+}
+
+pub fn db_thresholds(
+    conn: &Connection,
+    suite: &Suite,
+    sides: [(&str, &str); 2],
+) -> rusqlite::Result<Thresholds> {
+    let mut stmt = conn.prepare_cached(concat!(
+        "SELECT n.test_id, n.metric, MAX(n.noise) ",
+        "FROM noise n JOIN tests s ON s.id = n.test_id ",
+        "WHERE s.suite = ?1 ",
+        "AND ((n.machine = ?2 AND n.branch = ?3) OR (n.machine = ?4 AND n.branch = ?5)) ",
+        "GROUP BY n.test_id, n.metric"
+    ))?;
+    let noise = stmt
+        .query_map(
+            params![suite.name, sides[0].0, sides[0].1, sides[1].0, sides[1].1],
+            |row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)),
+        )?
+        .filter_map(|r| r.ok())
+        .collect();
+    let mut stmt = conn.prepare_cached(concat!(
+        "SELECT o.test_id, o.metric, o.threshold ",
+        "FROM threshold_overrides o JOIN tests s ON s.id = o.test_id ",
+        "WHERE s.suite = ?1"
+    ))?;
+    let overrides = stmt
+        .query_map(&[&suite.name], |row| {
+            Ok(((row.get(0)?, row.get(1)?), row.get(2)?))
+        })?
+        .filter_map(|r| r.ok())
+        .collect();
+    Ok(Thresholds { noise, overrides })
+}
+
+/// Sets the threshold of a test, for one metric or all of them. `None`
+/// returns to the noise-based threshold.
+pub fn set_override(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    suite: &str,
+    path: &str,
+    metric: Option<&str>,
+    threshold: Option<f64>,
+) -> Result<(), String> {
+    let suite = registry
+        .suite(suite)
+        .ok_or_else(|| format!("unknown suite '{}'", suite))?;
+    let metrics: Vec<&str> = match metric {
+        Some(name) => {
+            let metric = suite
+                .metrics
+                .iter()
+                .find(|m| m.name == name)
+                .ok_or_else(|| format!("unknown metric '{}' for suite '{}'", name, suite.name))?;
+            vec![metric.name.as_str()]
+        }
+        None => suite.metrics.iter().map(|m| m.name.as_str()).collect(),
+    };
+    let conn = db.get().map_err(|e| e.to_string())?;
+    let test_id = db_test_id(&conn, &suite.name, path)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("no test {} in suite '{}'", path, suite.name))?;
+    for metric in metrics {
+        match threshold {
+            Some(threshold) => conn.execute(
+                concat!(
+                    "INSERT OR REPLACE INTO threshold_overrides (test_id, metric, threshold) ",
+                    "VALUES (?1, ?2, ?3)"
+                ),
+                params![test_id, metric, threshold],
+            ),
+            None => conn.execute(
+                "DELETE FROM threshold_overrides WHERE test_id = ?1 AND metric = ?2",
+                params![test_id, metric],
+            ),
+        }
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_noise_relative_to_the_level() {
+        assert_eq!(relative_noise(&[100.0; 4]), None);
+        assert_eq!(relative_noise(&[100.0; 5]), Some(0.0));
+        let noise = relative_noise(&[98.0, 102.0, 98.0, 102.0, 98.0, 102.0]).unwrap();
+        assert!((noise - 4.0 / (0.6745 * 2f64.sqrt()) / 100.0).abs() < 1e-12);
+        assert_eq!(relative_noise(&[0.0; 5]), None);
+    }
+
+    #[test]
+    fn thresholds_follow_the_noise() {
+        let key = |test_id: i64, metric: &str| (test_id, metric.to_string());
+        let mut noise = HashMap::new();
+        noise.insert(key(1, "time"), 0.01);
+        noise.insert(key(2, "time"), 0.001);
+        noise.insert(key(3, "time"), 0.01);
+        let mut overrides = HashMap::new();
+        overrides.insert(key(3, "time"), 0.2);
+        let thresholds = Thresholds { noise, overrides };
+
+        let settings = Settings::default();
+        assert!((thresholds.threshold(&settings, 1, "time") - 0.03).abs() < 1e-12);
+        assert_eq!(
+            thresholds.threshold(&settings, 2, "time"),
+            settings.min_threshold
+        );
+        assert_eq!(thresholds.threshold(&settings, 3, "time"), 0.2);
+        assert_eq!(
+            thresholds.threshold(&settings, 4, "time"),
+            settings.threshold
+        );
+    }
+}
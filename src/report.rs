@@ -0,0 +1,346 @@
+use crate::branches::{branch_label, MAIN_LINE};
+use crate::comparison::{
+    db_revision_comparison, describe_failures, failure_messages, format_value, load_comparison,
+    regression_ratio, relative_change, side_labels, validate_revisions, Comparison, IndexQuery,
+    MetricComparison, Settings, Status,
+};
+use crate::error;
+use crate::metrics::{Metric, Registry, Suite};
+use crate::revisions::revision_label;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use std::cmp::Ordering;
+use std::fmt::Write;
+
+/// Number of regressions and improvements listed before the full tables.
+const TOP_CHANGES: usize = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Text,
+}
+
+impl Format {
+    pub fn from_arg(arg: Option<&str>) -> Result<Format, String> {
+        match arg {
+            None | Some("md") | Some("markdown") => Ok(Format::Markdown),
+            Some("text") | Some("txt") => Ok(Format::Text),
+            Some(other) => Err(format!("unknown report format '{}'", other)),
+        }
+    }
+}
+
+pub fn report_markdown(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
+    args: IndexQuery,
+) -> error::Result<String> {
+    let conn = db.get()?;
+    let comparison = load_comparison(&conn, registry, settings, args)?;
+    Ok(render(&comparison, Format::Markdown))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn report_for_revisions(
+    conn: &Connection,
+    registry: &Registry,
+    settings: &Settings,
+    machine: &str,
+    branch: &str,
+    revision1: &str,
+    revision2: &str,
+    format: Format,
+) -> Result<String, String> {
+    validate_revisions(conn, registry, machine, branch, &[revision1, revision2])?;
+    let suites = db_revision_comparison(
+        conn,
+        registry,
+        settings,
+        settings.aggregation,
+        machine,
+        branch,
+        revision1,
+        revision2,
+        "name",
+    )
+    .map_err(|e| e.to_string())?;
+    let comparison = Comparison {
+        revisions: vec![revision1.to_string(), revision2.to_string()],
+        revision_low: revision1.to_string(),
+        revision_high: revision2.to_string(),
+        sort: "name".to_string(),
+        aggregation: settings.aggregation,
+        machine: machine.to_string(),
+        machine2: machine.to_string(),
+        machines: vec![machine.to_string()],
+        branch: branch.to_string(),
+        branch2: branch.to_string(),
+        branches: vec![branch.to_string()],
+        suites,
+    };
+    Ok(render(&comparison, format))
+}
+
+struct Change<'a> {
+    suite: &'a Suite,
+    test: &'a str,
+    metric: &'a Metric,
+    m: &'a MetricComparison,
+}
+
+pub fn render(comparison: &Comparison, format: Format) -> String {
+    let mut changes: Vec<Change> = Vec::new();
+    let (mut tests, mut added, mut removed) = (0, 0, 0);
+    for suite in comparison.suites.iter() {
+        tests += suite.tests.len();
+        added += suite.added.len();
+        removed += suite.removed.len();
+        for test in suite.tests.iter() {
+            for (metric, m) in suite.suite.metrics.iter().zip(test.metrics.iter()) {
+                changes.push(Change {
+                    suite: suite.suite,
+                    test: &test.name,
+                    metric,
+                    m,
+                });
+            }
+        }
+    }
+    changes.sort_by(|a, b| {
+        regression_ratio(b.metric, b.m)
+            .partial_cmp(&regression_ratio(a.metric, a.m))
+            .unwrap_or(Ordering::Equal)
+    });
+    let regressions: Vec<_> = changes
+        .iter()
+        .filter(|c| c.m.status == Status::Regression)
+        .collect();
+    let improvements: Vec<_> = changes
+        .iter()
+        .rev()
+        .filter(|c| c.m.status == Status::Improvement)
+        .collect();
+
+    let (r1, r2) = side_labels(
+        (
+            &comparison.machine,
+            &comparison.branch,
+            &comparison.revision_low,
+        ),
+        (
+            &comparison.machine2,
+            &comparison.branch2,
+            &comparison.revision_high,
+        ),
+    );
+    let mut on_machine = String::new();
+    if comparison.machine != comparison.machine2 || comparison.branch != comparison.branch2 {
+        if comparison.revision_low == comparison.revision_high {
+            on_machine = format!(" at {}", revision_label(&comparison.revision_low));
+        }
+    } else {
+        if !comparison.machine.is_empty() {
+            on_machine = format!(" on {}", comparison.machine);
+        }
+        if comparison.branch != MAIN_LINE {
+            on_machine.push_str(&format!(" ({})", branch_label(&comparison.branch)));
+        }
+    }
+    let mut out = String::new();
+    let summary = format!(
+        "{} regressions, {} improvements across {} tests ({} of repetitions), {} added, {} removed",
+        regressions.len(),
+        improvements.len(),
+        tests,
+        comparison.aggregation.name(),
+        added,
+        removed
+    );
+    match format {
+        Format::Markdown => {
+            writeln!(out, "## Benchmarks {} vs {}{}\n", r1, r2, on_machine).unwrap();
+            writeln!(out, "{}\n", summary).unwrap();
+        }
+        Format::Text => {
+            writeln!(out, "Benchmarks {} vs {}{}\n", r1, r2, on_machine).unwrap();
+            writeln!(out, "{}\n", summary).unwrap();
+        }
+    }
+
+    let header = vec![
+        "Suite".to_string(),
+        "Test".to_string(),
+        "Metric".to_string(),
+        r1.clone(),
+        r2.clone(),
+        "Change".to_string(),
+    ];
+    let change_row = |c: &&Change| {
+        vec![
+            c.suite.name.clone(),
+            c.test.to_string(),
+            c.metric.name.clone(),
+            format_value(c.metric, c.m.value0),
+            format_value(c.metric, c.m.value1),
+            relative_change(c.m.value0, c.m.value1),
+        ]
+    };
+    // Tests that ran fine at r1 but had failed runs at r2.
+    let mut failures = vec![vec![
+        "Suite".to_string(),
+        "Test".to_string(),
+        "Failures".to_string(),
+        "Errors".to_string(),
+    ]];
+    for suite in comparison.suites.iter() {
+        for test in suite.tests.iter() {
+            if test.failures[0].is_empty() && !test.failures[1].is_empty() {
+                failures.push(vec![
+                    suite.suite.name.clone(),
+                    test.name.clone(),
+                    describe_failures(&test.failures[1]),
+                    failure_messages(&test.failures[1]).replace('\n', "; "),
+                ]);
+            }
+        }
+    }
+    if failures.len() > 1 {
+        section(&mut out, format, "New failures", &failures, false);
+    }
+
+    for (title, list) in [
+        ("Top regressions", &regressions),
+        ("Top improvements", &improvements),
+    ]
+    .iter()
+    {
+        if list.is_empty() {
+            continue;
+        }
+        let mut rows = vec![header.clone()];
+        rows.extend(list.iter().take(TOP_CHANGES).map(change_row));
+        section(&mut out, format, title, &rows, false);
+    }
+
+    for suite in comparison.suites.iter() {
+        let mut header = vec!["Test".to_string()];
+        header.extend(suite.suite.metrics.iter().map(|m| m.name.clone()));
+        let mut rows = vec![header];
+        for test in suite.tests.iter() {
+            let mut row = vec![test.name.clone()];
+            row.extend(
+                suite
+                    .suite
+                    .metrics
+                    .iter()
+                    .zip(test.metrics.iter())
+                    .map(|(metric, m)| {
+                        format!(
+                            "{} → {} ({})",
+                            format_value(metric, m.value0),
+                            format_value(metric, m.value1),
+                            relative_change(m.value0, m.value1)
+                        )
+                    }),
+            );
+            rows.push(row);
+        }
+        let title = format!("{} ({} tests)", suite.suite.title, suite.tests.len());
+        section(&mut out, format, &title, &rows, true);
+
+        for (title, tests) in [
+            (
+                format!("{}: added in {}", suite.suite.title, r2),
+                &suite.added,
+            ),
+            (
+                format!("{}: removed since {}", suite.suite.title, r1),
+                &suite.removed,
+            ),
+        ]
+        .iter()
+        {
+            if tests.is_empty() {
+                continue;
+            }
+            let mut header = vec!["Test".to_string()];
+            header.extend(suite.suite.metrics.iter().map(|m| m.name.clone()));
+            let mut rows = vec![header];
+            for test in tests.iter() {
+                let mut row = vec![test.name.clone()];
+                row.extend(
+                    suite
+                        .suite
+                        .metrics
+                        .iter()
+                        .zip(test.values.iter())
+                        .map(|(metric, v)| format_value(metric, *v)),
+                );
+                rows.push(row);
+            }
+            section(&mut out, format, title, &rows, false);
+        }
+    }
+    out
+}
+
+/// Writes a titled table. In Markdown, collapsed sections are wrapped in
+/// `<details>` so the full tables don't flood a review comment.
+fn section(out: &mut String, format: Format, title: &str, rows: &[Vec<String>], collapsed: bool) {
+    match format {
+        Format::Markdown if collapsed => {
+            writeln!(out, "<details><summary>{}</summary>\n", title).unwrap();
+            out.push_str(&markdown_table(rows));
+            writeln!(out, "\n</details>\n").unwrap();
+        }
+        Format::Markdown => {
+            writeln!(out, "### {}\n", title).unwrap();
+            out.push_str(&markdown_table(rows));
+            out.push('\n');
+        }
+        Format::Text => {
+            writeln!(out, "{}\n", title).unwrap();
+            out.push_str(&text_table(rows));
+            out.push('\n');
+        }
+    }
+}
+
+fn markdown_table(rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        let cells: Vec<_> = row.iter().map(|c| c.replace('|', "\\|")).collect();
+        writeln!(out, "| {} |", cells.join(" | ")).unwrap();
+        if i == 0 {
+            writeln!(out, "|{}", "---|".repeat(row.len())).unwrap();
+        }
+    }
+    out
+}
+
+/// Aligns columns with spaces. The first row is treated as header.
+pub fn text_table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|r| r.get(i))
+                .map(|c| c.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut out = String::new();
+    for row in rows {
+        let line: Vec<_> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:1$}", cell, width))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end()).unwrap();
+    }
+    out
+}
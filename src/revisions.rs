@@ -0,0 +1,142 @@
+use itertools::Itertools;
+use rusqlite::{params, Connection, NO_PARAMS};
+
+/// Commit hashes are shown abbreviated, like `git log --oneline` does.
+const SHORT_HASH_LENGTH: usize = 8;
+
+/// Display name of a revision: `r812345` for SVN revisions, the short hash
+/// for git commits.
+pub fn revision_label(revision: &str) -> String {
+    if is_svn_revision(revision) {
+        format!("r{}", revision)
+    } else {
+        revision.chars().take(SHORT_HASH_LENGTH).collect()
+    }
+}
+
+fn is_svn_revision(revision: &str) -> bool {
+    !revision.is_empty() && revision.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Revisions end up in URLs and pages, so only the characters of SVN
+/// revision numbers, commit hashes and tag-like names are accepted.
+pub fn is_valid_revision(revision: &str) -> bool {
+    !revision.is_empty()
+        && revision
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+/// Accepts SVN revision numbers as JSON numbers as well as strings.
+pub fn deserialize_revision<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Revision {
+        Number(u64),
+        Name(String),
+    }
+    Ok(
+        match <Revision as serde::Deserialize>::deserialize(deserializer)? {
+            Revision::Number(n) => n.to_string(),
+            Revision::Name(name) => name,
+        },
+    )
+}
+
+/// Where a revision belongs in the history, as reported along with its runs.
+/// Everything is optional: commits without a sequence number are placed
+/// after their parents, and all-digit revisions without either are taken for
+/// SVN revisions and ordered by their number.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Commit {
+    /// Position on the branch; larger is newer.
+    pub sequence: Option<i64>,
+    /// Unix timestamp.
+    pub commit_time: Option<i64>,
+    pub parents: Vec<String>,
+}
+
+pub fn db_revision_known(conn: &Connection, revision: &str) -> rusqlite::Result<bool> {
+    let known: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM revisions WHERE revision = ?1",
+        &[&revision],
+        |row| row.get(0),
+    )?;
+    Ok(known > 0)
+}
+
+/// Adds `revision` to the ordering table, or updates what is known about it.
+/// A revision belongs to the branch it was first seen on. Without SVN
+/// revision numbers, the sequence starts at `lowest_revision`. A commit with
+/// parents is never an SVN revision, even if its abbreviated hash happens to
+/// be all digits.
+pub fn db_record_revision(
+    conn: &Connection,
+    revision: &str,
+    branch: &str,
+    commit: &Commit,
+    lowest_revision: i64,
+) -> rusqlite::Result<()> {
+    let parents = commit.parents.iter().join(" ");
+    if db_revision_known(conn, revision)? {
+        conn.execute(
+            concat!(
+                "UPDATE revisions SET ",
+                "sequence = COALESCE(?2, sequence), ",
+                "commit_time = COALESCE(?3, commit_time), ",
+                "parents = CASE WHEN ?4 = '' THEN parents ELSE ?4 END ",
+                "WHERE revision = ?1"
+            ),
+            params![revision, commit.sequence, commit.commit_time, parents],
+        )?;
+        return Ok(());
+    }
+
+    let sequence = match commit.sequence {
+        Some(sequence) => sequence,
+        None if commit.parents.is_empty() && is_svn_revision(revision) => {
+            revision.parse().unwrap_or(0)
+        }
+        None => db_next_sequence(conn, &commit.parents, lowest_revision)?,
+    };
+    conn.execute(
+        concat!(
+            "INSERT INTO revisions (revision, branch, sequence, commit_time, parents) ",
+            "VALUES (?1, ?2, ?3, ?4, ?5)"
+        ),
+        params![revision, branch, sequence, commit.commit_time, parents],
+    )?;
+    Ok(())
+}
+
+/// Sequence number for a new commit: right after its newest known parent,
+/// or after the newest revision overall.
+fn db_next_sequence(
+    conn: &Connection,
+    parents: &[String],
+    lowest_revision: i64,
+) -> rusqlite::Result<i64> {
+    let mut newest_parent: Option<i64> = None;
+    for parent in parents {
+        let sequence: Option<i64> = conn.query_row(
+            "SELECT MAX(sequence) FROM revisions WHERE revision = ?1",
+            &[parent],
+            |row| row.get(0),
+        )?;
+        newest_parent = newest_parent.max(sequence);
+    }
+    match newest_parent {
+        Some(sequence) => Ok(sequence + 1),
+        None => {
+            let newest: Option<i64> =
+                conn.query_row("SELECT MAX(sequence) FROM revisions", NO_PARAMS, |row| {
+                    row.get(0)
+                })?;
+            Ok(newest.map_or(lowest_revision, |n| n + 1))
+        }
+    }
+}
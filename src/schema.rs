@@ -0,0 +1,391 @@
+use crate::metrics::Registry;
+use itertools::Itertools;
+use rusqlite::{Connection, NO_PARAMS};
+
+/// Each entry upgrades the schema by one version; the current version is kept
+/// in `PRAGMA user_version`. Never edit a migration that has been released,
+/// append a new one instead.
+static MIGRATIONS: &[&str] = &[
+    // 1: initial schema. `IF NOT EXISTS` adopts databases that predate migrations.
+    "CREATE TABLE IF NOT EXISTS processed_csb (
+        revision INTEGER NOT NULL,
+        config_file TEXT NOT NULL,
+        memory_peak REAL,
+        player_total_time REAL
+    );
+    CREATE TABLE IF NOT EXISTS processed_ini (
+        revision INTEGER NOT NULL,
+        config_file TEXT NOT NULL,
+        memory_peak REAL,
+        cutting_time REAL,
+        draw_time REAL
+    );
+    CREATE INDEX IF NOT EXISTS processed_csb_config_file_revision
+        ON processed_csb (config_file, revision);
+    CREATE INDEX IF NOT EXISTS processed_csb_revision ON processed_csb (revision);
+    CREATE INDEX IF NOT EXISTS processed_ini_config_file_revision
+        ON processed_ini (config_file, revision);
+    CREATE INDEX IF NOT EXISTS processed_ini_revision ON processed_ini (revision);",
+    // 2: detected step changes per test and metric.
+    "CREATE TABLE changepoints (
+        suite TEXT NOT NULL,
+        config_file TEXT NOT NULL,
+        metric TEXT NOT NULL,
+        revision INTEGER NOT NULL,
+        magnitude REAL NOT NULL,
+        confidence REAL NOT NULL
+    );
+    CREATE INDEX changepoints_suite_config_file ON changepoints (suite, config_file);",
+    // 3: outcome of each run. Failed runs have no or partial metric values.
+    "ALTER TABLE processed_csb ADD COLUMN status TEXT NOT NULL DEFAULT 'ok';
+    ALTER TABLE processed_csb ADD COLUMN error_message TEXT;
+    ALTER TABLE processed_ini ADD COLUMN status TEXT NOT NULL DEFAULT 'ok';
+    ALTER TABLE processed_ini ADD COLUMN error_message TEXT;",
+    // 4: the machine each run was measured on.
+    "ALTER TABLE processed_csb ADD COLUMN machine TEXT NOT NULL DEFAULT '';
+    ALTER TABLE processed_ini ADD COLUMN machine TEXT NOT NULL DEFAULT '';
+    ALTER TABLE changepoints ADD COLUMN machine TEXT NOT NULL DEFAULT '';
+    CREATE TABLE machines (
+        name TEXT PRIMARY KEY,
+        hostname TEXT,
+        cpu TEXT,
+        os TEXT,
+        build_config TEXT
+    );",
+    // 5: revisions are names (SVN numbers or git hashes), ordered by this
+    // table. Suite tables are converted by `ensure_suites`.
+    "CREATE TABLE revisions (
+        revision TEXT PRIMARY KEY,
+        branch TEXT NOT NULL DEFAULT '',
+        sequence INTEGER NOT NULL,
+        commit_time INTEGER,
+        parents TEXT NOT NULL DEFAULT ''
+    );
+    CREATE INDEX revisions_branch_sequence ON revisions (branch, sequence);
+    CREATE TABLE changepoints_text_revisions (
+        suite TEXT NOT NULL,
+        config_file TEXT NOT NULL,
+        machine TEXT NOT NULL DEFAULT '',
+        metric TEXT NOT NULL,
+        revision TEXT NOT NULL,
+        magnitude REAL NOT NULL,
+        confidence REAL NOT NULL
+    );
+    INSERT INTO changepoints_text_revisions
+        SELECT suite, config_file, machine, metric, CAST(revision AS TEXT), magnitude, confidence
+        FROM changepoints;
+    DROP TABLE changepoints;
+    ALTER TABLE changepoints_text_revisions RENAME TO changepoints;
+    CREATE INDEX changepoints_suite_config_file ON changepoints (suite, config_file);",
+    // 6: commit metadata imported from `svn log` or `git log`.
+    "CREATE TABLE commits (
+        revision TEXT PRIMARY KEY,
+        author TEXT NOT NULL,
+        date TEXT NOT NULL,
+        message TEXT NOT NULL
+    );",
+    // 7: change points are detected per branch. Suite tables get their
+    // `branch` column from `ensure_suites`.
+    "ALTER TABLE changepoints ADD COLUMN branch TEXT NOT NULL DEFAULT '';",
+    // 8: stable ids and display names of tests. Runs keep their raw
+    // `config_file`; existing tests are registered at startup.
+    "CREATE TABLE tests (
+        id INTEGER PRIMARY KEY,
+        suite TEXT NOT NULL,
+        path TEXT NOT NULL,
+        name TEXT NOT NULL,
+        UNIQUE (suite, path)
+    );",
+    // 9: renamed tests. Runs of `old_path` before `revision` belong to the
+    // test at `new_path`.
+    "CREATE TABLE test_aliases (
+        suite TEXT NOT NULL,
+        old_path TEXT NOT NULL,
+        new_path TEXT NOT NULL,
+        revision TEXT NOT NULL,
+        PRIMARY KEY (suite, old_path)
+    );",
+    // 10: per-test thresholds. `noise` is estimated during change point
+    // detection, overrides are set from the command line.
+    "CREATE TABLE noise (
+        test_id INTEGER NOT NULL,
+        machine TEXT NOT NULL,
+        branch TEXT NOT NULL,
+        metric TEXT NOT NULL,
+        noise REAL NOT NULL,
+        samples INTEGER NOT NULL,
+        PRIMARY KEY (test_id, machine, branch, metric)
+    );
+    CREATE TABLE threshold_overrides (
+        test_id INTEGER NOT NULL,
+        metric TEXT NOT NULL,
+        threshold REAL NOT NULL,
+        PRIMARY KEY (test_id, metric)
+    );",
+];
+
+/// Columns every suite table has besides its metric columns, as
+/// `(name, definition)` for `ALTER TABLE ... ADD COLUMN`.
+static BASE_COLUMNS: &[(&str, &str)] = &[
+    ("status", "status TEXT NOT NULL DEFAULT 'ok'"),
+    ("error_message", "error_message TEXT"),
+    ("machine", "machine TEXT NOT NULL DEFAULT ''"),
+    ("branch", "branch TEXT NOT NULL DEFAULT ''"),
+];
+
+/// Whether a metric column would collide with a column of every suite table.
+pub fn is_reserved_column(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name == "revision"
+        || name == "config_file"
+        || BASE_COLUMNS.iter().any(|(column, _)| *column == name)
+}
+
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<usize> {
+    let version: i64 = conn.query_row("PRAGMA user_version", NO_PARAMS, |r| r.get(0))?;
+    Ok(version as usize)
+}
+
+/// Applies all pending migrations, each in its own transaction.
+/// Returns the number of migrations that were applied.
+pub fn migrate(conn: &mut Connection) -> Result<usize, String> {
+    let current = schema_version(conn).map_err(|e| e.to_string())?;
+    if current > MIGRATIONS.len() {
+        return Err(format!(
+            "database schema version {} is newer than this binary (version {})",
+            current,
+            MIGRATIONS.len()
+        ));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        apply(conn, migration, i + 1)
+            .map_err(|e| format!("migration to version {} failed: {}", i + 1, e))?;
+    }
+    Ok(MIGRATIONS.len() - current)
+}
+
+fn apply(conn: &mut Connection, migration: &str, version: usize) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(migration)?;
+    tx.execute_batch(&format!("PRAGMA user_version = {}", version))?;
+    tx.commit()
+}
+
+/// Creates tables and metric columns for suites declared in `config.toml`
+/// that the migrations don't know about.
+pub fn ensure_suites(conn: &Connection, registry: &Registry) -> rusqlite::Result<()> {
+    for suite in registry.suites.iter() {
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                revision TEXT NOT NULL,
+                config_file TEXT NOT NULL
+            );",
+            suite.table
+        ))?;
+        let existing = table_columns(conn, &suite.table)?;
+        if existing
+            .iter()
+            .any(|c| c.name == "revision" && c.definition_type != "TEXT")
+        {
+            convert_revisions_to_text(conn, &suite.table, &existing)?;
+        }
+        conn.execute_batch(&format!(
+            "CREATE INDEX IF NOT EXISTS {table}_config_file_revision
+                ON {table} (config_file, revision);
+            CREATE INDEX IF NOT EXISTS {table}_revision ON {table} (revision);",
+            table = suite.table
+        ))?;
+        let has_column = |name: &str| existing.iter().any(|c| c.name == name);
+        for (column, definition) in BASE_COLUMNS.iter() {
+            if !has_column(column) {
+                conn.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN {}",
+                    suite.table, definition
+                ))?;
+            }
+        }
+        for metric in suite.metrics.iter() {
+            if !has_column(&metric.column) {
+                conn.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN {} REAL",
+                    suite.table, metric.column
+                ))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Suite tables used to store SVN revision numbers as integers. Since git
+/// commits are keyed by hash, the table is rebuilt with a text column and
+/// its revisions are added to the ordering table. SQLite can't change a
+/// column's type in place.
+fn convert_revisions_to_text(
+    conn: &Connection,
+    table: &str,
+    columns: &[Column],
+) -> rusqlite::Result<()> {
+    let definitions = columns
+        .iter()
+        .map(|c| {
+            let mut definition = format!(
+                "{} {}",
+                c.name,
+                if c.name == "revision" {
+                    "TEXT"
+                } else {
+                    &c.definition_type
+                }
+            );
+            if c.not_null {
+                definition.push_str(" NOT NULL");
+            }
+            if let Some(default) = &c.default {
+                definition.push_str(&format!(" DEFAULT {}", default));
+            }
+            definition
+        })
+        .join(", ");
+    let names = columns.iter().map(|c| &c.name).join(", ");
+    let values = columns
+        .iter()
+        .map(|c| {
+            if c.name == "revision" {
+                "CAST(revision AS TEXT)".to_string()
+            } else {
+                c.name.clone()
+            }
+        })
+        .join(", ");
+    // `ensure_suites` only has a shared connection; the transaction rolls
+    // back when dropped on an error.
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(&format!(
+        "CREATE TABLE {table}_text_revisions ({definitions});
+        INSERT INTO {table}_text_revisions ({names}) SELECT {values} FROM {table};
+        DROP TABLE {table};
+        ALTER TABLE {table}_text_revisions RENAME TO {table};
+        INSERT OR IGNORE INTO revisions (revision, sequence)
+            SELECT DISTINCT revision, CAST(revision AS INTEGER) FROM {table};",
+        table = table,
+        definitions = definitions,
+        names = names,
+        values = values
+    ))?;
+    tx.commit()
+}
+
+struct Column {
+    name: String,
+    definition_type: String,
+    not_null: bool,
+    default: Option<String>,
+}
+
+fn table_columns(conn: &Connection, table: &str) -> rusqlite::Result<Vec<Column>> {
+    Ok(conn
+        .prepare(&format!("PRAGMA table_info({})", table))?
+        .query_map(NO_PARAMS, |row| {
+            Ok(Column {
+                name: row.get(1)?,
+                definition_type: row.get(2)?,
+                not_null: row.get(3)?,
+                default: row.get(4)?,
+            })
+        })?
+        .filter_map(|r| r.ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_a_new_database_once() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&mut conn), Ok(MIGRATIONS.len()));
+        assert_eq!(schema_version(&conn), Ok(MIGRATIONS.len()));
+        assert_eq!(migrate(&mut conn), Ok(0));
+    }
+
+    #[test]
+    fn adopts_databases_that_predate_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE processed_csb (
+                revision INTEGER NOT NULL,
+                config_file TEXT NOT NULL,
+                memory_peak REAL,
+                player_total_time REAL
+            );
+            INSERT INTO processed_csb VALUES (812345, 'a.csb', 512, 1.5);",
+        )
+        .unwrap();
+        assert_eq!(migrate(&mut conn), Ok(MIGRATIONS.len()));
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM processed_csb", NO_PARAMS, |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn converts_integer_revisions_to_text() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE processed_csb (
+                revision INTEGER NOT NULL,
+                config_file TEXT NOT NULL,
+                memory_peak REAL,
+                player_total_time REAL
+            );
+            INSERT INTO processed_csb VALUES (812345, 'a.csb', 512, 1.5);",
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+        let registry = Registry::new(Vec::new(), Vec::new()).unwrap();
+        ensure_suites(&conn, &registry).unwrap();
+        // A second start finds the table converted already.
+        ensure_suites(&conn, &registry).unwrap();
+
+        let columns = table_columns(&conn, "processed_csb").unwrap();
+        let revision = columns.iter().find(|c| c.name == "revision").unwrap();
+        assert_eq!(
+            (revision.definition_type.as_str(), revision.not_null),
+            ("TEXT", true)
+        );
+        let status = columns.iter().find(|c| c.name == "status").unwrap();
+        assert_eq!(status.default.as_deref(), Some("'ok'"));
+        let (revision, kind, time): (String, String, f64) = conn
+            .query_row(
+                "SELECT revision, typeof(revision), player_total_time FROM processed_csb",
+                NO_PARAMS,
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (revision.as_str(), kind.as_str(), time),
+            ("812345", "text", 1.5)
+        );
+        let sequence: i64 = conn
+            .query_row(
+                "SELECT sequence FROM revisions WHERE revision = '812345'",
+                NO_PARAMS,
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(sequence, 812_345);
+    }
+
+    #[test]
+    fn refuses_newer_schemas() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len() + 1))
+            .unwrap();
+        assert!(migrate(&mut conn)
+            .unwrap_err()
+            .contains("newer than this binary"));
+    }
+}
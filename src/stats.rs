@@ -0,0 +1,336 @@
+pub fn mean(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+/// Unbiased sample variance.
+pub fn variance(xs: &[f64]) -> f64 {
+    let m = mean(xs);
+    xs.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / (xs.len() as f64 - 1.0)
+}
+
+/// Standard deviation of the noise around a piecewise constant level, from
+/// the median of the absolute first differences. Unlike the sample standard
+/// deviation, it is robust against the occasional level shift. NaN for fewer
+/// than two values.
+pub fn difference_sigma(xs: &[f64]) -> f64 {
+    if xs.len() < 2 {
+        return f64::NAN;
+    }
+    let mut diffs: Vec<f64> = xs.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+    diffs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    median(&diffs) / (0.6745 * 2f64.sqrt())
+}
+
+pub struct Welch {
+    /// Two-sided p-value for the hypothesis that both samples have the same mean.
+    pub p_value: f64,
+    /// Confidence interval of `mean(b) / mean(a) - 1`.
+    pub relative_change_ci: (f64, f64),
+}
+
+/// Welch's unequal variances t-test. Returns `None` if either sample has
+/// fewer than two values.
+#[allow(clippy::float_cmp)]
+pub fn welch(a: &[f64], b: &[f64], confidence: f64) -> Option<Welch> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let (n1, n2) = (a.len() as f64, b.len() as f64);
+    let (m1, m2) = (mean(a), mean(b));
+    let (se1, se2) = (variance(a) / n1, variance(b) / n2);
+    let relative_change = m2 / m1 - 1.0;
+
+    if se1 + se2 == 0.0 {
+        let p_value = if m1 == m2 { 1.0 } else { 0.0 };
+        return Some(Welch {
+            p_value,
+            relative_change_ci: (relative_change, relative_change),
+        });
+    }
+
+    let t = (m2 - m1) / (se1 + se2).sqrt();
+    let df = (se1 + se2).powi(2) / (se1 * se1 / (n1 - 1.0) + se2 * se2 / (n2 - 1.0));
+
+    // Delta method for the variance of the ratio of means.
+    let ratio = m2 / m1;
+    let ratio_se = ratio.abs() * (se1 / (m1 * m1) + se2 / (m2 * m2)).sqrt();
+    let t_crit = t_quantile(1.0 - confidence, df);
+
+    Some(Welch {
+        p_value: t_two_sided_p(t, df),
+        relative_change_ci: (
+            relative_change - t_crit * ratio_se,
+            relative_change + t_crit * ratio_se,
+        ),
+    })
+}
+
+/// Two-sided tail probability of Student's t distribution.
+fn t_two_sided_p(t: f64, df: f64) -> f64 {
+    incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// Finds `t` such that `t_two_sided_p(t, df) == p` by bisection.
+fn t_quantile(p: f64, df: f64) -> f64 {
+    let (mut lo, mut hi) = (0.0, 1.0e3);
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if t_two_sided_p(mid, df) > p {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// Regularized incomplete beta function I_x(a, b) (Numerical Recipes, 6.4).
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1.0e-30;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..300 {
+        let m = f64::from(m);
+        let m2 = 2.0 * m;
+        let aa = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 + aa * d;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = 1.0 + aa / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        h *= d * c;
+        let aa = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 + aa * d;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = 1.0 + aa / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 3.0e-14 {
+            break;
+        }
+    }
+    h
+}
+
+/// Lanczos approximation of ln(Γ(x)) for x > 0.
+#[allow(clippy::excessive_precision)]
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.120_865_097_386_617_9e-2,
+        -0.539_523_938_495_3e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut y = x;
+    let mut series = 1.000_000_000_190_015;
+    for c in COEFFICIENTS.iter() {
+        y += 1.0;
+        series += c / y;
+    }
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    Mean,
+    Median,
+    Min,
+    /// Mean of the values left after dropping the lowest and highest 20%.
+    TrimmedMean,
+}
+
+impl Aggregation {
+    pub const ALL: [Aggregation; 4] = [
+        Aggregation::Mean,
+        Aggregation::Median,
+        Aggregation::Min,
+        Aggregation::TrimmedMean,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aggregation::Mean => "mean",
+            Aggregation::Median => "median",
+            Aggregation::Min => "min",
+            Aggregation::TrimmedMean => "trimmed_mean",
+        }
+    }
+
+    /// Parses an optional query parameter, falling back to `default`.
+    pub fn from_arg(arg: Option<&str>, default: Aggregation) -> Result<Aggregation, String> {
+        match arg {
+            None => Ok(default),
+            Some(name) => Self::ALL
+                .iter()
+                .cloned()
+                .find(|a| a.name() == name)
+                .ok_or_else(|| format!("unknown aggregation '{}'", name)),
+        }
+    }
+
+    /// Returns NaN for an empty slice.
+    pub fn apply(self, xs: &[f64]) -> f64 {
+        if xs.is_empty() {
+            return f64::NAN;
+        }
+        let mut sorted = xs.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        match self {
+            Aggregation::Mean => mean(xs),
+            Aggregation::Median => median(&sorted),
+            Aggregation::Min => sorted[0],
+            Aggregation::TrimmedMean => {
+                let cut = sorted.len() / 5;
+                mean(&sorted[cut..sorted.len() - cut])
+            }
+        }
+    }
+}
+
+/// Median of an already sorted slice.
+pub fn median(sorted: &[f64]) -> f64 {
+    let n = sorted.len();
+    if n % 2 == 1 {
+        sorted[n / 2]
+    } else {
+        (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn ln_gamma_matches_factorials() {
+        assert_close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln(), 1e-8);
+        assert_close(ln_gamma(1.0), 0.0, 1e-8);
+        assert_close(ln_gamma(10.0), 362_880f64.ln(), 1e-8);
+    }
+
+    #[test]
+    fn incomplete_beta_known_values() {
+        assert_close(incomplete_beta(2.0, 3.0, 0.4), 0.5248, 1e-8);
+        assert_close(incomplete_beta(1.0, 1.0, 0.3), 0.3, 1e-8);
+        assert_close(incomplete_beta(2.0, 3.0, 0.0), 0.0, 0.0);
+        assert_close(incomplete_beta(2.0, 3.0, 1.0), 1.0, 0.0);
+    }
+
+    #[test]
+    fn t_distribution_matches_tables() {
+        assert_close(t_quantile(0.05, 4.0), 2.776, 1e-3);
+        assert_close(t_quantile(0.01, 10.0), 3.169, 1e-3);
+        assert_close(t_two_sided_p(2.776_445, 4.0), 0.05, 1e-5);
+        assert_close(t_two_sided_p(0.0, 4.0), 1.0, 1e-12);
+    }
+
+    #[test]
+    fn welch_textbook_example() {
+        let a = [
+            27.5, 21.0, 19.0, 23.6, 17.0, 17.9, 16.9, 20.1, 21.9, 22.6, 23.1, 19.6, 19.0, 21.7,
+            21.4,
+        ];
+        let b = [
+            27.1, 22.0, 20.8, 23.4, 23.4, 23.5, 25.8, 22.0, 24.8, 20.2, 21.9, 22.1, 22.9, 20.5,
+            24.4,
+        ];
+        let w = welch(&a, &b, 0.95).unwrap();
+        assert_close(w.p_value, 0.021_378, 1e-5);
+        let relative_change = mean(&b) / mean(&a) - 1.0;
+        assert!(w.relative_change_ci.0 > 0.0);
+        assert!(w.relative_change_ci.0 < relative_change);
+        assert!(w.relative_change_ci.1 > relative_change);
+    }
+
+    #[test]
+    fn welch_zero_variance() {
+        let same = welch(&[2.0, 2.0], &[2.0, 2.0, 2.0], 0.95).unwrap();
+        assert_close(same.p_value, 1.0, 0.0);
+        assert_close(same.relative_change_ci.0, 0.0, 0.0);
+        assert_close(same.relative_change_ci.1, 0.0, 0.0);
+
+        let shifted = welch(&[2.0, 2.0], &[3.0, 3.0], 0.95).unwrap();
+        assert_close(shifted.p_value, 0.0, 0.0);
+        assert_close(shifted.relative_change_ci.0, 0.5, 0.0);
+        assert_close(shifted.relative_change_ci.1, 0.5, 0.0);
+    }
+
+    #[test]
+    fn welch_needs_two_values_per_side() {
+        assert!(welch(&[1.0], &[1.0, 2.0], 0.95).is_none());
+        assert!(welch(&[1.0, 2.0], &[], 0.95).is_none());
+    }
+
+    #[test]
+    fn aggregations() {
+        let xs = [4.0, 1.0, 3.0, 2.0, 100.0];
+        assert_close(Aggregation::Mean.apply(&xs), 22.0, 1e-12);
+        assert_close(Aggregation::Median.apply(&xs), 3.0, 0.0);
+        assert_close(Aggregation::Median.apply(&[4.0, 1.0, 3.0, 2.0]), 2.5, 0.0);
+        assert_close(Aggregation::Min.apply(&xs), 1.0, 0.0);
+        // Drops the lowest and the highest value of five.
+        assert_close(Aggregation::TrimmedMean.apply(&xs), 3.0, 1e-12);
+        // Fewer than five values: nothing to drop.
+        assert_close(
+            Aggregation::TrimmedMean.apply(&[1.0, 2.0, 3.0, 10.0]),
+            4.0,
+            1e-12,
+        );
+        assert!(Aggregation::Median.apply(&[]).is_nan());
+    }
+
+    #[test]
+    fn difference_sigma_ignores_level_shifts() {
+        assert!(difference_sigma(&[1.0]).is_nan());
+        assert_close(difference_sigma(&[5.0; 10]), 0.0, 0.0);
+        // Alternating by 2 around each level, with one jump in between.
+        let xs = [0.0, 2.0, 0.0, 2.0, 0.0, 102.0, 100.0, 102.0, 100.0, 102.0];
+        assert_close(difference_sigma(&xs), 2.0 / (0.6745 * 2f64.sqrt()), 1e-12);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::branches::{branch_label, MAIN_LINE};
+use crate::commits::{db_commits, CommitMetadata, CommitSettings};
+use crate::comparison::{describe_failures, failure_messages, format_value, Settings};
+use crate::error;
+use crate::graphs::{db_revision_history_for_file, parse_aggregation, RevisionInfos};
+use crate::machines::db_test_series;
+use crate::metrics::{Metric, Registry, Suite};
+use crate::revisions::revision_label;
+use crate::stats::{self, Aggregation};
+use crate::testcases::{self, Test};
+use itertools::Itertools;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+pub struct TestQuery {
+    /// Defaults to the first machine that ran the test.
+    machine: Option<String>,
+    /// Defaults to the main line.
+    branch: Option<String>,
+    agg: Option<String>,
+}
+
+/// URL of the detail page of a test, relative to the comparison page.
+pub fn test_url(id: i64, machine: &str, branch: &str) -> String {
+    format!(
+        "test/{}?machine={}&branch={}",
+        id,
+        url_encode(machine),
+        url_encode(branch)
+    )
+}
+
+fn url_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub fn test_page(
+    db: &Pool<SqliteConnectionManager>,
+    registry: &Registry,
+    settings: &Settings,
+    commit_settings: &CommitSettings,
+    id: i64,
+    query: TestQuery,
+) -> error::Result<String> {
+    let aggregation = parse_aggregation(query.agg.as_deref(), settings)?;
+    let conn = db.get()?;
+    let (test, suite) = testcases::find(&conn, registry, id)?;
+    let series = db_test_series(&conn, suite, &test.path)?;
+    let branch = query.branch.unwrap_or_else(|| MAIN_LINE.to_string());
+    let machine = query
+        .machine
+        .or_else(|| {
+            series
+                .iter()
+                .find(|(_, b)| *b == branch)
+                .map(|(m, _)| m.clone())
+        })
+        .ok_or_else(|| {
+            error::Error::NotFound(format!(
+                "no runs of test '{}' on {}",
+                test.name,
+                branch_label(&branch)
+            ))
+        })?;
+
+    let mut history = db_revision_history_for_file(
+        &conn,
+        suite,
+        &test.path,
+        &machine,
+        &branch,
+        settings.lowest_revision,
+        aggregation,
+    )?;
+    // Newest first.
+    history.reverse();
+    let revisions: Vec<&str> = history.iter().map(|r| r.revision.as_str()).collect();
+    let commits = db_commits(&conn, &revisions)?;
+
+    Ok(TestDetail {
+        page: &Page {
+            suite,
+            test: &test,
+            machine: &machine,
+            branch: &branch,
+            aggregation,
+            machines: series.iter().map(|(m, _)| m.as_str()).unique().collect(),
+            branches: series.iter().map(|(_, b)| b.as_str()).unique().collect(),
+            history: &history,
+            commits: &commits,
+            commit_settings,
+        },
+    }
+    .to_string())
+}
+
+pub struct Page<'a> {
+    suite: &'a Suite,
+    test: &'a Test,
+    machine: &'a str,
+    branch: &'a str,
+    aggregation: Aggregation,
+    /// Machines and branches that ran the test, for the selects.
+    machines: Vec<&'a str>,
+    branches: Vec<&'a str>,
+    /// Newest first.
+    history: &'a [RevisionInfos],
+    commits: &'a HashMap<String, CommitMetadata>,
+    commit_settings: &'a CommitSettings,
+}
+
+impl<'a> Page<'a> {
+    fn run_count(&self) -> usize {
+        self.history
+            .iter()
+            .map(|r| r.samples.iter().map(Vec::len).max().unwrap_or(0) + r.failures.len())
+            .sum()
+    }
+
+    fn failure_count(&self) -> usize {
+        self.history.iter().map(|r| r.failures.len()).sum()
+    }
+}
+
+/// Min, max and standard deviation of the repetitions of one revision. The
+/// standard deviation needs at least two of them.
+fn spread(samples: &[f64]) -> (f64, f64, f64) {
+    if samples.is_empty() {
+        return (f64::NAN, f64::NAN, f64::NAN);
+    }
+    let min = samples.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = samples.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let std_dev = if samples.len() < 2 {
+        f64::NAN
+    } else {
+        stats::variance(samples).sqrt()
+    };
+    (min, max, std_dev)
+}
+
+markup::define! {
+    TestDetail<'a>(page: &'a Page<'a>) {
+        {markup::doctype()}
+        html {
+            head {
+                title { {&page.test.name} " – " {&page.suite.title} }
+                script[src="../static/Chart.min.js"] {}
+                script[src="../static/table.js"] {}
+                link[rel="stylesheet", href="../static/style.css"] {}
+                link[rel="icon", type="image/png", href="../static/icon.png"] {}
+            }
+            body[
+                "data-aggregation" = page.aggregation.name(),
+                "data-machine" = page.machine,
+                "data-branch" = page.branch
+            ] {
+                p { a[href="../"] { "Back to the comparison" } }
+                h1 { {&page.suite.title} ": " {&page.test.name} }
+                p { {&page.test.path} }
+                {TestForm { page }}
+                p {
+                    {page.run_count()} " runs of " {page.history.len()} " revisions on "
+                    {page.machine} " (" {branch_label(page.branch)} ")"
+                    @if page.failure_count() > 0 {
+                        ", " {page.failure_count()} " failed"
+                    }
+                }
+                div[
+                    class = "chart",
+                    "data-autoload" = true,
+                    "data-suite" = &page.suite.name,
+                    "data-chart-id" = page.test.id,
+                    "data-machine" = page.machine,
+                    "data-width" = 1000,
+                    "data-height" = 300
+                ] {}
+                @for (i, metric) in page.suite.metrics.iter().enumerate() {
+                    {MetricHistory { page, metric, i }}
+                }
+            }
+        }
+    }
+
+    TestForm<'a>(page: &'a Page<'a>) {
+        form {
+            " Machine: "
+            select[name="machine"] {
+                @for m in page.machines.iter() {
+                    option[selected? = *m == page.machine] { {m} }
+                }
+            }
+            " Branch: "
+            select[name="branch"] {
+                @for b in page.branches.iter() {
+                    option[value = b, selected? = *b == page.branch] { {branch_label(b)} }
+                }
+            }
+            " Aggregate: "
+            select[name="agg"] {
+                @for a in Aggregation::ALL.iter() {
+                    option[selected? = *a == page.aggregation] { {a.name()} }
+                }
+            }
+            " "
+            input[type="submit", value="Ok"] {}
+        }
+    }
+
+    MetricHistory<'a>(page: &'a Page<'a>, metric: &'a Metric, i: usize) {
+        h2 { {&metric.title} }
+        table.benchtable {
+            thead {
+                tr {
+                    th { "Revision" }
+                    th { "Runs" }
+                    th { {page.aggregation.name()} }
+                    th { "Min" }
+                    th { "Max" }
+                    th { "Std dev" }
+                    th { "Repetitions" }
+                    th { "Failures" }
+                    th { "Commit" }
+                }
+            }
+            tbody {
+                @for r in page.history.iter() {
+                    {HistoryRow {
+                        page,
+                        metric,
+                        revision: r,
+                        samples: &r.samples[*i],
+                        stat: r.stats[*i],
+                        spread: spread(&r.samples[*i])
+                    }}
+                }
+            }
+        }
+    }
+
+    HistoryRow<'a>(
+        page: &'a Page<'a>,
+        metric: &'a Metric,
+        revision: &'a RevisionInfos,
+        samples: &'a [f64],
+        stat: f64,
+        spread: (f64, f64, f64)
+    ) {
+        tr {
+            td {
+                @if let Some(url) = page.commit_settings.link(&revision.revision) {
+                    a[href = url] { {revision_label(&revision.revision)} }
+                } else {
+                    {revision_label(&revision.revision)}
+                }
+            }
+            td { {samples.len()} }
+            td { {format_value(metric, *stat)} }
+            td { {format_value(metric, spread.0)} }
+            td { {format_value(metric, spread.1)} }
+            td { {format_value(metric, spread.2)} }
+            td { {samples.iter().map(|v| metric.format(*v)).join(", ")} }
+            td[title = failure_messages(&revision.failures)] {
+                @if !revision.failures.is_empty() {
+                    {describe_failures(&revision.failures)}
+                }
+            }
+            td {
+                @if let Some(commit) = page.commits.get(&revision.revision) {
+                    span[title = &commit.message] { {commit.subject()} }
+                }
+            }
+        }
+    }
+}
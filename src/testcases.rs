@@ -0,0 +1,150 @@
+use crate::error;
+use crate::metrics::{Registry, Suite};
+use rusqlite::{params, Connection, NO_PARAMS};
+use std::collections::HashMap;
+
+/// Step of turning the path a test was reported with into its display name.
+/// Rules are applied in order.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameRule {
+    /// Drops everything up to and including the last occurrence.
+    StripThrough(String),
+    StripPrefix(String),
+    StripSuffix(String),
+    /// Replaces every occurrence of the first string by the second.
+    Replace(String, String),
+}
+
+impl NameRule {
+    fn apply(&self, name: &str) -> String {
+        match self {
+            NameRule::StripThrough(s) => match name.rfind(s.as_str()) {
+                Some(i) => name[i + s.len()..].to_string(),
+                None => name.to_string(),
+            },
+            NameRule::StripPrefix(s) if name.starts_with(s.as_str()) => name[s.len()..].to_string(),
+            NameRule::StripSuffix(s) if name.ends_with(s.as_str()) => {
+                name[..name.len() - s.len()].to_string()
+            }
+            NameRule::StripPrefix(_) | NameRule::StripSuffix(_) => name.to_string(),
+            NameRule::Replace(from, to) => name.replace(from.as_str(), to),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TestSettings {
+    /// E.g. `name_rules = [{ strip_through = "/testcases/" }]`.
+    pub name_rules: Vec<NameRule>,
+}
+
+impl Default for TestSettings {
+    /// The test paths of the CSB and INI suites are absolute Windows paths.
+    fn default() -> Self {
+        Self {
+            name_rules: vec![NameRule::StripThrough("\\testcases\\".to_string())],
+        }
+    }
+}
+
+pub fn canonical_name(rules: &[NameRule], path: &str) -> String {
+    rules
+        .iter()
+        .fold(path.to_string(), |name, rule| rule.apply(&name))
+}
+
+/// A test of a suite. Runs refer to it by `path`, everything else by `id`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Test {
+    pub id: i64,
+    pub suite: String,
+    /// The `config_file` its runs were reported with.
+    pub path: String,
+    pub name: String,
+}
+
+/// Adds the test to the tests table, or updates its name if the rules
+/// changed.
+pub fn db_register(
+    conn: &Connection,
+    registry: &Registry,
+    suite: &str,
+    path: &str,
+) -> rusqlite::Result<()> {
+    conn.prepare_cached(concat!(
+        "INSERT INTO tests (suite, path, name) VALUES (?1, ?2, ?3) ",
+        "ON CONFLICT (suite, path) DO UPDATE SET name = excluded.name"
+    ))?
+    .execute(params![suite, path, registry.test_name(path)])?;
+    Ok(())
+}
+
+/// Registers the tests of all runs. Runs inserted before the tests table
+/// existed get their ids here, and names follow changed rules.
+pub fn db_register_all(conn: &mut Connection, registry: &Registry) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    for suite in registry.suites.iter() {
+        let paths: Vec<String> = tx
+            .prepare(&format!("SELECT DISTINCT config_file FROM {}", suite.table))?
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+        for path in paths {
+            db_register(&tx, registry, &suite.name, &path)?;
+        }
+    }
+    tx.commit()
+}
+
+pub fn db_test(conn: &Connection, id: i64) -> rusqlite::Result<Option<Test>> {
+    let mut stmt = conn.prepare_cached("SELECT suite, path, name FROM tests WHERE id = ?1")?;
+    let mut rows = stmt.query_map(&[&id], |row| {
+        Ok(Test {
+            id,
+            suite: row.get(0)?,
+            path: row.get(1)?,
+            name: row.get(2)?,
+        })
+    })?;
+    rows.next().transpose()
+}
+
+/// Looks up a test by the id given to a web route, along with its suite.
+pub fn find<'a>(
+    conn: &Connection,
+    registry: &'a Registry,
+    id: i64,
+) -> error::Result<(Test, &'a Suite)> {
+    let test = db_test(conn, id)?
+        .ok_or_else(|| error::Error::NotFound(format!("unknown test id {}", id)))?;
+    let suite = registry.suite(&test.suite).ok_or_else(|| {
+        error::Error::NotFound(format!(
+            "test {} belongs to unknown suite '{}'",
+            id, test.suite
+        ))
+    })?;
+    Ok((test, suite))
+}
+
+/// `path -> (id, name)` of the tests of a suite.
+pub fn db_suite_tests(
+    conn: &Connection,
+    suite: &str,
+) -> rusqlite::Result<HashMap<String, (i64, String)>> {
+    let mut stmt = conn.prepare_cached("SELECT path, id, name FROM tests WHERE suite = ?1")?;
+    let tests = stmt
+        .query_map(&[&suite], |row| {
+            Ok((row.get(0)?, (row.get(1)?, row.get(2)?)))
+        })?
+        .filter_map(|r| r.ok())
+        .collect();
+    Ok(tests)
+}
+
+pub fn db_test_id(conn: &Connection, suite: &str, path: &str) -> rusqlite::Result<Option<i64>> {
+    let mut stmt = conn.prepare_cached("SELECT id FROM tests WHERE suite = ?1 AND path = ?2")?;
+    let mut rows = stmt.query_map(&[suite, path], |row| row.get(0))?;
+    rows.next().transpose()
+}
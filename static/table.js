@@ -1,3 +1,9 @@
+function escapeHtml(text)
+{
+	var node = document.createElement('span');
+	node.textContent = text;
+	return node.innerHTML;
+}
 function addGraph(node, data, for_single_file)
 {
 	var ctx = node.getContext('2d');
@@ -35,11 +41,15 @@ function addGraph(node, data, for_single_file)
 		{
 			var dataset = data.datasets[bodyItem.datasetIndex];
 			var itemData = dataset.data[bodyItem.index];
-			if (bodyItem.datasetIndex != 0)
-				return dataset.label + ': ' + Math.round(itemData['v'] * 100) / 100 + ' s';
+			if (!itemData)
+				return dataset.label;
+			if (itemData['failure'])
+				return dataset.label + ': ' + itemData['failure'];
+			if (dataset.unit == 'MB')
+				return dataset.label + ': ' + Math.round(itemData['v']) + ' MB';
 			else
 			{
-				return dataset.label + ': ' + Math.round(itemData['v']) + ' MB';
+				return dataset.label + ': ' + Math.round(itemData['v'] * 100) / 100 + ' ' + dataset.unit;
 			}
 		}
 
@@ -52,6 +62,9 @@ function addGraph(node, data, for_single_file)
 			var innerHtml = '<thead>';
 
 			titleLines.forEach(function(title) { innerHtml += '<tr><th>' + title + '</th></tr>'; });
+			var subject = data.subjects && tooltip.dataPoints.length > 0 ? data.subjects[tooltip.dataPoints[0].index] : null;
+			if (subject)
+				innerHtml += '<tr><td><i>' + escapeHtml(subject) + '</i></td></tr>';
 			innerHtml += '</thead><tbody>';
 
 			bodyLines.forEach(function(body, i) {
@@ -114,21 +127,21 @@ function loadChart(chartNode)
 			var data = JSON.parse(this.responseText)
 			var node = document.createElement('canvas');
 			node.classList = ['chartContainer'];
-			node.width = 500;
-			node.height = 150;
+			node.width = chartNode.getAttribute('data-width') || 500;
+			node.height = chartNode.getAttribute('data-height') || 150;
 			chartNode.appendChild(node);
 			addGraph(node, data, true);
 		}
 	};
 	var chartId = encodeURI(chartNode.getAttribute('data-chart-id'));
-	if (chartId.includes('.csb'))
-	{
-		xhttp.open('GET', '/api/file/csb?id=%' + chartId, true);
-	}
-	else
-	{
-		xhttp.open('GET', '/api/file/ini?id=%' + chartId, true);
-	}
+	var suite = chartNode.getAttribute('data-suite');
+	var agg = document.body.getAttribute('data-aggregation');
+	var branch = encodeURIComponent(document.body.getAttribute('data-branch'));
+	var url = '/api/file/' + suite + '?id=' + chartId + '&agg=' + agg + '&branch=' + branch;
+	var machine = chartNode.getAttribute('data-machine');
+	if (machine !== null)
+		url += '&machine=' + encodeURIComponent(machine);
+	xhttp.open('GET', url, true);
 	xhttp.send();
 }
 function loadSummaryChart(type, r1, r2)
@@ -142,29 +155,33 @@ function loadSummaryChart(type, r1, r2)
 			addGraph(node, data, false);
 		}
 	};
-	xhttp.open('GET', '/api/all/' + type + '?r1=' + r1 + '&r2=' + r2, true);
+	var agg = document.body.getAttribute('data-aggregation');
+	var machine = encodeURIComponent(document.body.getAttribute('data-machine'));
+	var branch = encodeURIComponent(document.body.getAttribute('data-branch'));
+	xhttp.open('GET', '/api/all/' + type + '?r1=' + encodeURIComponent(r1) + '&r2=' + encodeURIComponent(r2) + '&agg=' + agg + '&machine=' + machine + '&branch=' + branch, true);
 	xhttp.send();
 }
 function loadSummaryCharts(r1, r2)
 {
-	document.getElementById('summary_charts').innerHTML = '<h1>Graphs</h1>\
-            <h2>CSB Play Time</h2>\
-            <canvas id="csb_play_time_graph" width="500" height="100"></canvas>\
-            <h2>CSB Memory</h2>\
-            <canvas id="csb_memory_graph" width="500" height="100"></canvas>\
-            <h2>Ini Cutting Time</h2>\
-            <canvas id="ini_cut_time_graph" width="500" height="100"></canvas>\
-            <h2>Ini Draw Time</h2>\
-            <canvas id="ini_draw_time_graph" width="500" height="100"></canvas>\
-            <h2>Ini Memory</h2>\
-            <canvas id="ini_memory_graph" width="500" height="100"></canvas>';
-	loadSummaryChart('csb_play_time', r1, r2);
-	loadSummaryChart('csb_memory', r1, r2);
-	loadSummaryChart('ini_cut_time', r1, r2);
-	loadSummaryChart('ini_draw_time', r1, r2);
-	loadSummaryChart('ini_memory', r1, r2);
+	var container = document.getElementById('summary_charts');
+	var graphs = JSON.parse(container.getAttribute('data-graphs'));
+	var innerHtml = '<h1>Graphs</h1>';
+	for (let graph of graphs)
+	{
+		innerHtml += '<h2>' + escapeHtml(graph.title) + '</h2>';
+		innerHtml += '<canvas id="' + graph.id + '_graph" width="500" height="100"></canvas>';
+	}
+	container.innerHTML = innerHtml;
+	for (let graph of graphs)
+	{
+		loadSummaryChart(graph.id, r1, r2);
+	}
 }
 window.onload = function() {
+	for (let chart of document.querySelectorAll('.chart[data-autoload]'))
+	{
+		loadChart(chart);
+	}
 	for (let element of document.querySelectorAll('.toggle-table'))
 	{
 		let name = element.parentElement.getAttribute('data-js-name');